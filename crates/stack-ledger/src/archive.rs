@@ -0,0 +1,36 @@
+use crate::{Error, StackLedger};
+
+impl StackLedger {
+
+    /// Moves up to `batch_size` entries written before `created_before` (a unix timestamp) from
+    /// `ledger` to `ledger_archive`, keeping the newest entry of every account and stack. History,
+    /// lineage and audit reads go through the `ledger_history` view and still see them. Can run
+    /// while the server is live, call it until it returns 0
+    pub async fn archive_batch(&self, created_before: i64, batch_size: i64) -> Result<u64, Error> {
+
+        let archived = sqlx::query!(
+            r#"WITH archivable AS (
+                SELECT ledger.account_id, ledger.key
+                FROM ledger
+                JOIN latest ON latest.account_id = ledger.account_id AND latest.stack_uuid = ledger.stack_uuid
+                WHERE ledger.created_at < $1 AND ledger.sequence_number < latest.sequence_number
+                LIMIT $2
+            ), moved AS (
+                DELETE FROM ledger
+                USING archivable
+                WHERE ledger.account_id = archivable.account_id AND ledger.key = archivable.key
+                RETURNING ledger.key, ledger.account_id, ledger.stack_uuid, ledger.sequence_number, ledger.composite, ledger.qty, ledger.balance,
+                ledger.item_type, ledger.operation, ledger.reason, ledger.key_version, ledger.entry_hash, ledger.created_at
+            )
+            INSERT INTO ledger_archive (key, account_id, stack_uuid, sequence_number, composite, qty, balance, item_type, operation, reason, key_version, entry_hash, created_at)
+            SELECT key, account_id, stack_uuid, sequence_number, composite, qty, balance, item_type, operation, reason, key_version, entry_hash, created_at
+            FROM moved;"#,
+            created_before,
+            batch_size)
+            .execute(&self.backend)
+            .await?;
+
+        Ok(archived.rows_affected())
+    }
+
+}
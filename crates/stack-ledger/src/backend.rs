@@ -0,0 +1,105 @@
+use std::future::Future;
+use sqlx::{PgPool, Transaction, Postgres};
+use crate::{freeze, AccountId, CraftInput, Error, Reason, ReceiptEntry, Stack, StackLedger, StackUuid};
+
+/// Storage behind a `StackLedger`. Every implementation must keep the same invariants as the
+/// Postgres schema: a stack uuid is created only once, balances never go below zero and every
+/// (account, stack) pair gets consecutive sequence numbers starting at 0
+pub trait LedgerBackend: Send + Sync {
+
+    type Tx<'a>: LedgerTx where Self: 'a;
+
+    fn begin(&self) -> impl Future<Output = Result<Self::Tx<'_>, Error>> + Send;
+
+    fn freeze_account(&self, account_id: &AccountId, reason: &str, expires_at: Option<i64>) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn unfreeze_account(&self, account_id: &AccountId) -> impl Future<Output = Result<(), Error>> + Send;
+
+}
+
+/// Nothing written through a `LedgerTx` is visible to others until `commit`, dropping it rolls
+/// everything back
+///
+/// Every write takes an optional `expected_sequence_number` for each account it writes to and fails
+/// with `Error::StaleVersion` if that account's stack is at another version, checked while the stack
+/// is locked. Credits and creates also pass if the account doesn't hold the stack yet. Every write
+/// returns the ledger entries it inserted
+pub trait LedgerTx: Send {
+
+    fn create(&mut self, stack_uuid: StackUuid, item_type: i32, expected_sequence_number: Option<i32>, qty: u64, account_id: &AccountId, reason: Option<&Reason>) -> impl Future<Output = Result<ReceiptEntry, Error>> + Send;
+
+    fn destroy(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> impl Future<Output = Result<ReceiptEntry, Error>> + Send;
+
+    fn admin_destroy(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> impl Future<Output = Result<ReceiptEntry, Error>> + Send;
+
+    /// Returns the sender's entry, then the recipient's
+    #[allow(clippy::too_many_arguments)]
+    fn split(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> impl Future<Output = Result<(ReceiptEntry, ReceiptEntry), Error>> + Send;
+
+    #[allow(clippy::too_many_arguments)]
+    fn admin_split(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> impl Future<Output = Result<(ReceiptEntry, ReceiptEntry), Error>> + Send;
+
+    /// Current version of the stack, no other transaction can change it until this one ends
+    fn lock_stack(&mut self, account_id: &AccountId, stack_uuid: StackUuid) -> impl Future<Output = Result<Option<Stack>, Error>> + Send;
+
+    /// Links a crafted stack to the inputs its craft destroyed
+    fn record_craft(&mut self, output_uuid: StackUuid, inputs: &[CraftInput]) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn commit(self) -> impl Future<Output = Result<(), Error>> + Send;
+
+}
+
+impl LedgerBackend for PgPool {
+
+    type Tx<'a> = Transaction<'static, Postgres>;
+
+    async fn begin(&self) -> Result<Self::Tx<'_>, Error> {
+        Ok(PgPool::begin(self).await?)
+    }
+
+    async fn freeze_account(&self, account_id: &AccountId, reason: &str, expires_at: Option<i64>) -> Result<(), Error> {
+        freeze::freeze_account(self, account_id, reason, expires_at).await
+    }
+
+    async fn unfreeze_account(&self, account_id: &AccountId) -> Result<(), Error> {
+        freeze::unfreeze_account(self, account_id).await
+    }
+
+}
+
+impl LedgerTx for Transaction<'_, Postgres> {
+
+    async fn create(&mut self, stack_uuid: StackUuid, item_type: i32, expected_sequence_number: Option<i32>, qty: u64, account_id: &AccountId, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        StackLedger::create(self, stack_uuid, item_type, expected_sequence_number, qty, account_id, reason).await
+    }
+
+    async fn destroy(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        StackLedger::destroy(self, stack_uuid, expected_item_type, expected_sequence_number, account_id, qty, reason).await
+    }
+
+    async fn admin_destroy(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        StackLedger::admin_destroy(self, stack_uuid, expected_item_type, expected_sequence_number, account_id, qty, reason).await
+    }
+
+    async fn split(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<(ReceiptEntry, ReceiptEntry), Error> {
+        StackLedger::split(self, stack_uuid, expected_item_type, expected_sequence_number, expected_recipient_sequence_number, sender_id, recipient_id, qty, reason).await
+    }
+
+    async fn admin_split(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<(ReceiptEntry, ReceiptEntry), Error> {
+        StackLedger::admin_split(self, stack_uuid, expected_item_type, expected_sequence_number, expected_recipient_sequence_number, sender_id, recipient_id, qty, reason).await
+    }
+
+    async fn lock_stack(&mut self, account_id: &AccountId, stack_uuid: StackUuid) -> Result<Option<Stack>, Error> {
+        StackLedger::lock_stack(self, account_id, stack_uuid).await
+    }
+
+    async fn record_craft(&mut self, output_uuid: StackUuid, inputs: &[CraftInput]) -> Result<(), Error> {
+        StackLedger::record_craft(self, output_uuid, inputs).await
+    }
+
+    async fn commit(self) -> Result<(), Error> {
+        Transaction::commit(self).await?;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,429 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::backend::{LedgerBackend, LedgerTx};
+use crate::{check_expected_version, checked_qty, AccountId, Error, Operation, Reason, Stack, StackLedger, StackUuid};
+
+/// Extra rule checked over the whole batch, on top of the per stack checks. Both compare each item
+/// type with itself, so they can't hold for a craft turning some item types into others, and
+/// batches with a `Reason::Recipe` reject them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conservation {
+    /// Every item type is created exactly as much as it is destroyed
+    Balanced,
+    /// No item type is created more than it is destroyed
+    NoMint,
+}
+
+#[derive(Clone, Debug)]
+enum BatchOp {
+    Create {
+        stack_uuid: StackUuid,
+        item_type: i32,
+        expected_sequence_number: Option<i32>,
+        account_id: AccountId,
+        qty: u64,
+    },
+    Destroy {
+        stack_uuid: StackUuid,
+        expected_item_type: i32,
+        expected_sequence_number: Option<i32>,
+        account_id: AccountId,
+        qty: u64,
+    },
+    Split {
+        stack_uuid: StackUuid,
+        expected_item_type: i32,
+        expected_sequence_number: Option<i32>,
+        expected_recipient_sequence_number: Option<i32>,
+        sender_id: AccountId,
+        recipient_id: AccountId,
+        qty: u64,
+    },
+}
+
+/// Operations that are validated together and written in one transaction, in the order they were
+/// added
+#[derive(Clone, Debug, Default)]
+pub struct LedgerBatch {
+    ops: Vec<BatchOp>,
+    reason: Option<Reason>,
+    conservation: Option<Conservation>,
+}
+
+/// A ledger entry as it was inserted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiptEntry {
+    pub(crate) key: i64,
+    pub(crate) account_id: AccountId,
+    pub(crate) stack_uuid: StackUuid,
+    pub(crate) sequence_number: i32,
+    pub(crate) qty: i64,
+    pub(crate) balance: i64,
+    pub(crate) item_type: i32,
+    pub(crate) operation: Operation,
+    pub(crate) entry_hash: Vec<u8>,
+}
+
+impl ReceiptEntry {
+
+    pub fn get_key(&self) -> i64 {
+        self.key
+    }
+
+    pub fn get_account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    pub fn get_uuid(&self) -> StackUuid {
+        self.stack_uuid
+    }
+
+    pub fn get_sequence_number(&self) -> i32 {
+        self.sequence_number
+    }
+
+    pub fn get_qty(&self) -> i64 {
+        self.qty
+    }
+
+    pub fn get_balance(&self) -> i64 {
+        self.balance
+    }
+
+    pub fn get_type(&self) -> i32 {
+        self.item_type
+    }
+
+    pub fn get_operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn get_entry_hash(&self) -> &[u8] {
+        &self.entry_hash
+    }
+
+}
+
+/// Every ledger entry a batch wrote, in order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatchReceipt {
+    entries: Vec<ReceiptEntry>,
+}
+
+impl BatchReceipt {
+
+    pub fn get_entries(&self) -> &[ReceiptEntry] {
+        &self.entries
+    }
+
+}
+
+/// What the batch will have done to the stacks it touches, `None` for stacks the account doesn't hold
+struct Plan {
+    stacks: HashMap<(AccountId, StackUuid), Option<Stack>>,
+    created: HashSet<StackUuid>,
+}
+
+impl Plan {
+
+    async fn load<T: LedgerTx>(&mut self, tx: &mut T, account_id: &AccountId, stack_uuid: StackUuid) -> Result<Option<Stack>, Error> {
+        let key = (account_id.clone(), stack_uuid);
+        if let Some(stack) = self.stacks.get(&key) {
+            return Ok(stack.clone());
+        }
+
+        let stack = tx.lock_stack(account_id, stack_uuid).await?;
+        self.stacks.insert(key, stack.clone());
+        Ok(stack)
+    }
+
+    fn write(&mut self, account_id: &AccountId, stack: Stack) {
+        self.stacks.insert((account_id.clone(), stack.stack_uuid), Some(stack));
+    }
+
+    async fn create<T: LedgerTx>(&mut self, tx: &mut T, stack_uuid: StackUuid, item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: i64) -> Result<(), Error> {
+
+        if expected_sequence_number.is_some() {
+            let actual = self.load(tx, account_id, stack_uuid).await?
+                .map(|stack| stack.sequence_number);
+            check_expected_version(expected_sequence_number, actual, account_id, stack_uuid)?;
+        }
+
+        // The consumed table only catches uuids created by other transactions
+        if !self.created.insert(stack_uuid) {
+            return Err(Error::AlreadyConsumed { stack_uuid });
+        }
+
+        let stack = Stack {
+            stack_uuid,
+            sequence_number: 0,
+            balance: qty,
+            item_type,
+        };
+        self.write(account_id, stack);
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn debit<T: LedgerTx>(&mut self, tx: &mut T, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: i64) -> Result<(), Error> {
+
+        let stack = self.load(tx, account_id, stack_uuid).await?
+            .ok_or_else(|| Error::StackNotFound {
+                account_id: account_id.clone(),
+                stack_uuid,
+            })?;
+
+        if let Some(expected) = expected_sequence_number
+            && expected != stack.sequence_number {
+            return Err(Error::StaleVersion {
+                account_id: account_id.clone(),
+                stack_uuid,
+                expected,
+                actual: stack.sequence_number,
+            });
+        }
+
+        if expected_item_type != stack.item_type {
+            return Err(Error::ItemTypeMismatch {
+                account_id: account_id.clone(),
+                stack_uuid,
+                expected: expected_item_type,
+                actual: stack.item_type,
+            });
+        }
+
+        if qty > stack.balance {
+            return Err(Error::NotEnoughBalance {
+                account_id: account_id.clone(),
+                stack_uuid,
+                qty,
+                balance: stack.balance,
+            });
+        }
+
+        let stack = Stack {
+            sequence_number: stack.sequence_number + 1,
+            balance: stack.balance - qty,
+            ..stack
+        };
+        self.write(account_id, stack);
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn credit<T: LedgerTx>(&mut self, tx: &mut T, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, recipient_id: &AccountId, qty: i64) -> Result<(), Error> {
+
+        let stack = match self.load(tx, recipient_id, stack_uuid).await? {
+            Some(stack) => {
+                check_expected_version(expected_sequence_number, Some(stack.sequence_number), recipient_id, stack_uuid)?;
+                let balance = stack.balance
+                    .checked_add(qty)
+                    .ok_or_else(|| Error::BalanceOverflow {
+                        account_id: recipient_id.clone(),
+                        stack_uuid,
+                        qty,
+                        balance: stack.balance,
+                    })?;
+                Stack {
+                    sequence_number: stack.sequence_number + 1,
+                    balance,
+                    ..stack
+                }
+            },
+            None => Stack {
+                stack_uuid,
+                sequence_number: 0,
+                balance: qty,
+                item_type: expected_item_type,
+            },
+        };
+        self.write(recipient_id, stack);
+        Ok(())
+    }
+
+}
+
+impl LedgerBatch {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `expected_sequence_number` passes if the account doesn't hold the stack yet, which is always
+    /// the case for a new uuid
+    pub fn create(mut self, stack_uuid: StackUuid, item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64) -> Self {
+        self.ops.push(BatchOp::Create {
+            stack_uuid,
+            item_type,
+            expected_sequence_number,
+            account_id: account_id.clone(),
+            qty,
+        });
+        self
+    }
+
+    /// `expected_sequence_number` is checked against the stack as it is when the operation runs,
+    /// after the earlier operations of the batch
+    pub fn destroy(mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64) -> Self {
+        self.ops.push(BatchOp::Destroy {
+            stack_uuid,
+            expected_item_type,
+            expected_sequence_number,
+            account_id: account_id.clone(),
+            qty,
+        });
+        self
+    }
+
+    /// `expected_sequence_number` is the sender's version of the stack, checked like in `destroy`.
+    /// `expected_recipient_sequence_number` is the recipient's, which also passes if the recipient
+    /// doesn't hold the stack yet
+    #[allow(clippy::too_many_arguments)]
+    pub fn split(mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64) -> Self {
+        self.ops.push(BatchOp::Split {
+            stack_uuid,
+            expected_item_type,
+            expected_sequence_number,
+            expected_recipient_sequence_number,
+            sender_id: sender_id.clone(),
+            recipient_id: recipient_id.clone(),
+            qty,
+        });
+        self
+    }
+
+    /// Written on every entry of the batch
+    pub fn reason(mut self, reason: Reason) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    pub fn conservation(mut self, conservation: Conservation) -> Self {
+        self.conservation = Some(conservation);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn check_conservation(&self) -> Result<(), Error> {
+
+        let Some(conservation) = self.conservation else {
+            return Ok(());
+        };
+
+        if let Some(Reason::Recipe { recipe_id, .. }) = self.reason {
+            return Err(Error::ConservationOnCraft {
+                recipe_id,
+                conservation,
+            });
+        }
+
+        // (created, destroyed) per item type
+        let mut totals: BTreeMap<i32, (i64, i64)> = BTreeMap::new();
+        for op in &self.ops {
+            let (item_type, qty, created) = match op {
+                BatchOp::Create { item_type, qty, .. } => (*item_type, *qty, true),
+                BatchOp::Destroy { expected_item_type, qty, .. } => (*expected_item_type, *qty, false),
+                BatchOp::Split { .. } => continue,
+            };
+
+            let qty = checked_qty(qty)?;
+            let (created_total, destroyed_total) = totals.entry(item_type).or_insert((0, 0));
+            let total = if created { created_total } else { destroyed_total };
+            *total = total
+                .checked_add(qty)
+                .ok_or(Error::TotalOverflow { item_type })?;
+        }
+
+        for (item_type, (created, destroyed)) in totals {
+            let violated = match conservation {
+                Conservation::Balanced => created != destroyed,
+                Conservation::NoMint => created > destroyed,
+            };
+            if violated {
+                return Err(Error::ConservationViolated {
+                    item_type,
+                    created,
+                    destroyed,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs every operation against the locked stacks, so nothing is written unless the whole
+    /// batch holds
+    async fn plan<T: LedgerTx>(&self, tx: &mut T) -> Result<(), Error> {
+
+        let mut plan = Plan {
+            stacks: HashMap::new(),
+            created: HashSet::new(),
+        };
+
+        for op in &self.ops {
+            match op {
+                BatchOp::Create { stack_uuid, item_type, expected_sequence_number, account_id, qty } => {
+                    plan.create(tx, *stack_uuid, *item_type, *expected_sequence_number, account_id, checked_qty(*qty)?).await?;
+                },
+                BatchOp::Destroy { stack_uuid, expected_item_type, expected_sequence_number, account_id, qty } => {
+                    plan.debit(tx, *stack_uuid, *expected_item_type, *expected_sequence_number, account_id, checked_qty(*qty)?).await?;
+                },
+                BatchOp::Split { stack_uuid, expected_item_type, expected_sequence_number, expected_recipient_sequence_number, sender_id, recipient_id, qty } => {
+                    let qty = checked_qty(*qty)?;
+                    plan.debit(tx, *stack_uuid, *expected_item_type, *expected_sequence_number, sender_id, qty).await?;
+                    plan.credit(tx, *stack_uuid, *expected_item_type, *expected_recipient_sequence_number, recipient_id, qty).await?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates and writes the batch inside `tx`, so it can be combined with other writes.
+    /// Frozen accounts and already consumed uuids are still only caught while writing
+    pub async fn apply<T: LedgerTx>(&self, tx: &mut T) -> Result<BatchReceipt, Error> {
+
+        self.check_conservation()?;
+        self.plan(tx).await?;
+        let reason = self.reason.as_ref();
+        let mut entries = Vec::with_capacity(self.ops.len());
+
+        for op in &self.ops {
+            match op {
+                BatchOp::Create { stack_uuid, item_type, expected_sequence_number, account_id, qty } => {
+                    entries.push(tx.create(*stack_uuid, *item_type, *expected_sequence_number, *qty, account_id, reason).await?);
+                },
+                BatchOp::Destroy { stack_uuid, expected_item_type, expected_sequence_number, account_id, qty } => {
+                    entries.push(tx.destroy(*stack_uuid, *expected_item_type, *expected_sequence_number, account_id, *qty, reason).await?);
+                },
+                BatchOp::Split { stack_uuid, expected_item_type, expected_sequence_number, expected_recipient_sequence_number, sender_id, recipient_id, qty } => {
+                    let (debit, credit) = tx.split(*stack_uuid, *expected_item_type, *expected_sequence_number, *expected_recipient_sequence_number, sender_id, recipient_id, *qty, reason).await?;
+                    entries.push(debit);
+                    entries.push(credit);
+                },
+            }
+        }
+
+        Ok(BatchReceipt { entries })
+    }
+
+}
+
+impl<B: LedgerBackend> StackLedger<B> {
+
+    /// Runs the batch in its own transaction
+    pub async fn execute_batch(&self, batch: &LedgerBatch) -> Result<BatchReceipt, Error> {
+        let mut tx = self.backend.begin().await?;
+        let receipt = batch.apply(&mut tx).await?;
+        let account_ids: Vec<&AccountId> = receipt.entries.iter().map(|entry| &entry.account_id).collect();
+        self.commit(tx, &account_ids).await?;
+        Ok(receipt)
+    }
+
+}
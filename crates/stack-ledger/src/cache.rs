@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use crate::{AccountId, Error, Inventory, InventoryManager};
+
+/// Channel the triggers on `latest` and `inventories` notify with the account_id as payload
+pub(crate) const INVENTORY_CHANNEL: &str = "inventory_changed";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    invalidations: u64,
+}
+
+impl CacheStats {
+
+    pub fn get_hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn get_misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn get_evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    pub fn get_invalidations(&self) -> u64 {
+        self.invalidations
+    }
+
+}
+
+enum Slot {
+    /// A miss is reading the inventory, the token tells it apart from reads started before an
+    /// invalidation
+    Loading(u64),
+    Ready(Inventory),
+}
+
+struct CacheEntry {
+    slot: Slot,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<AccountId, CacheEntry>,
+    by_last_used: BTreeMap<u64, AccountId>,
+    tick: u64,
+}
+
+impl CacheState {
+
+    fn touch(&mut self, account_id: &AccountId) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(account_id) {
+            self.by_last_used.remove(&entry.last_used);
+            entry.last_used = tick;
+            self.by_last_used.insert(tick, account_id.clone());
+        }
+    }
+
+    fn remove(&mut self, account_id: &AccountId) -> bool {
+        match self.entries.remove(account_id) {
+            Some(entry) => {
+                self.by_last_used.remove(&entry.last_used);
+                true
+            },
+            None => false,
+        }
+    }
+
+}
+
+/// Least recently used inventories, bounded to `capacity` accounts
+pub(crate) struct InventoryCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+    listening: AtomicBool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl InventoryCache {
+
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(CacheState::default()),
+            listening: AtomicBool::new(false),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached inventory, or the token to pass to `fill` once it was read
+    pub(crate) fn get(&self, account_id: &AccountId) -> Result<Inventory, u64> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(CacheEntry { slot: Slot::Ready(inventory), .. }) = state.entries.get(account_id) {
+            let inventory = inventory.clone();
+            state.touch(account_id);
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(inventory);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        state.tick += 1;
+        let token = state.tick;
+
+        state.remove(account_id);
+        while state.entries.len() >= self.capacity {
+            let Some((_, oldest)) = state.by_last_used.pop_first() else {
+                break;
+            };
+            state.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        state.entries.insert(account_id.clone(), CacheEntry {
+            slot: Slot::Loading(token),
+            last_used: token,
+        });
+        state.by_last_used.insert(token, account_id.clone());
+
+        Err(token)
+    }
+
+    /// Only stores the inventory if nothing invalidated the account since `get` handed out `token`
+    pub(crate) fn fill(&self, account_id: &AccountId, token: u64, inventory: &Inventory) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(entry) = state.entries.get_mut(account_id)
+            && matches!(entry.slot, Slot::Loading(loading) if loading == token) {
+            entry.slot = Slot::Ready(inventory.clone());
+        }
+    }
+
+    pub(crate) fn invalidate(&self, account_id: &AccountId) {
+        if self.state.lock().unwrap().remove(account_id) {
+            self.invalidations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        let cleared = state.entries.len() as u64;
+        state.entries.clear();
+        state.by_last_used.clear();
+        self.invalidations.fetch_add(cleared, Ordering::Relaxed);
+    }
+
+    /// Only a cache that receives invalidations can be read
+    pub(crate) fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::Acquire)
+    }
+
+    fn start_listening(&self) {
+        self.clear();
+        self.listening.store(true, Ordering::Release);
+    }
+
+    fn stop_listening(&self) {
+        self.listening.store(false, Ordering::Release);
+        self.clear();
+    }
+
+    pub(crate) fn get_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+}
+
+impl InventoryManager {
+
+    /// Like `new`, but keeps up to `capacity` inventories in memory. Nothing is cached unless
+    /// `listen_for_invalidations` is running and connected
+    pub async fn with_cache(pool: PgPool, capacity: usize) -> Self {
+        Self {
+            pool,
+            cache: Some(Arc::new(InventoryCache::new(capacity))),
+            replica: None,
+        }
+    }
+
+    pub fn get_cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.get_stats())
+    }
+
+    /// Drops the cached inventory of every account the ledger changes, from any server. Reconnects
+    /// whenever the connection fails and bypasses the cache until it is back. Runs until the pool
+    /// is closed, so spawn it next to the manager
+    pub async fn listen_for_invalidations(&self) -> Result<(), Error> {
+
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
+
+        loop {
+            let result = self.listen(cache).await;
+            // Notifications may be missed until it listens again
+            cache.stop_listening();
+
+            if let Err(Error::Sqlx(sqlx::Error::PoolClosed)) = result {
+                return Ok(());
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Returns once the connection is lost
+    async fn listen(&self, cache: &InventoryCache) -> Result<(), Error> {
+
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(INVENTORY_CHANNEL).await?;
+
+        // Whatever was cached before listening may already be stale
+        cache.start_listening();
+
+        while let Some(notification) = listener.try_recv().await? {
+            match notification.payload().parse::<AccountId>() {
+                Ok(account_id) => cache.invalidate(&account_id),
+                Err(_) => cache.clear(),
+            }
+        }
+        Ok(())
+    }
+
+}
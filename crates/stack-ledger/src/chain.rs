@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use sqlx::PgExecutor;
+use crate::history::fetch_history;
+use crate::keys::KeyInput;
+use crate::merkle::matches_commitment;
+use crate::{AccountId, InventoryManager, LedgerEntry, Operation, Reason, StackUuid};
+
+/// Previous hash of the first chained entry of a stack
+pub(crate) const GENESIS_HASH: [u8; 32] = [0; 32];
+
+const CHAIN_DOMAIN: &[u8] = b"clusterium.ledger_chain";
+
+const VERIFY_PAGE_SIZE: i64 = 1000;
+
+/// What goes into `ledger.entry_hash`. Entries are chained per account and stack, the same rows
+/// `latest` locks, so the shared world accounts don't serialize every drop on a single chain
+pub(crate) struct ChainedEntry<'a> {
+    pub(crate) account_id: &'a AccountId,
+    pub(crate) stack_uuid: StackUuid,
+    pub(crate) sequence_number: i32,
+    pub(crate) qty: i64,
+    pub(crate) balance: i64,
+    pub(crate) item_type: i32,
+    pub(crate) operation: Operation,
+    pub(crate) reason: Option<&'a Reason>,
+}
+
+fn operation_tag(operation: Operation) -> &'static [u8] {
+    match operation {
+        Operation::Create => b"create",
+        Operation::Destroy => b"destroy",
+        Operation::SplitOut => b"split_out",
+        Operation::SplitIn => b"split_in",
+    }
+}
+
+impl ChainedEntry<'_> {
+
+    pub(crate) fn hash(&self, prev_hash: &[u8]) -> [u8; 32] {
+        let reason = bincode::serialize(&self.reason).expect("reasons always serialize");
+
+        let input = KeyInput::with_domain(CHAIN_DOMAIN)
+            .field(prev_hash)
+            .field(self.account_id.as_bytes())
+            .field(&self.stack_uuid.to_le_bytes())
+            .field(&self.sequence_number.to_le_bytes())
+            .field(&self.qty.to_le_bytes())
+            .field(&self.balance.to_le_bytes())
+            .field(&self.item_type.to_le_bytes())
+            .field(operation_tag(self.operation))
+            .field(&reason);
+        Sha256::digest(input.as_bytes()).into()
+    }
+
+}
+
+impl LedgerEntry {
+
+    fn chained(&self) -> ChainedEntry<'_> {
+        ChainedEntry {
+            account_id: &self.account_id,
+            stack_uuid: self.stack_uuid,
+            sequence_number: self.sequence_number,
+            qty: self.qty,
+            balance: self.balance,
+            item_type: self.item_type,
+            operation: self.operation,
+            reason: self.get_reason(),
+        }
+    }
+
+}
+
+/// Where `InventoryManager::verify_chain` found the history edited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainBreak {
+    /// The first entry that doesn't match its hash. It was edited, or entries of its stack right
+    /// before it were removed
+    Entry {
+        key: i64,
+    },
+    /// The newest entries of the stack were removed, or `latest` doesn't point at the last one
+    Head {
+        stack_uuid: StackUuid,
+    },
+    /// The `latest` rows don't match the account's stored Merkle root, e.g. a stack was removed
+    /// together with all its entries
+    Commitment,
+}
+
+async fn fetch_chain_heads<'e, E: PgExecutor<'e>>(executor: E, account_id: &AccountId) -> Result<Vec<(StackUuid, Vec<u8>)>, sqlx::Error> {
+
+    let rows = sqlx::query!(
+        r#"SELECT stack_uuid AS "stack_uuid: StackUuid", entry_hash AS "entry_hash!"
+        FROM latest
+        WHERE account_id = $1 AND entry_hash IS NOT NULL;"#,
+        account_id as &AccountId)
+        .fetch_all(executor)
+        .await?;
+
+    Ok(rows.into_iter().map(|row| (row.stack_uuid, row.entry_hash)).collect())
+}
+
+impl InventoryManager {
+
+    /// Recomputes every hash chain of the account and checks their heads against the account's
+    /// Merkle root, all from one snapshot. Entries written before the chain existed are skipped.
+    /// Anyone with write access can rebuild consistent chains and root, so for disputes compare
+    /// against a root exported with `get_commitment` beforehand, it covers the head of every stack
+    pub async fn verify_chain(&self, account_id: &AccountId) -> Result<Option<ChainBreak>, sqlx::Error> {
+
+        let mut tx = self.read_pool(Some(account_id)).await.begin().await?;
+        sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY;")
+            .execute(&mut *tx)
+            .await?;
+
+        let mut heads: HashMap<StackUuid, [u8; 32]> = HashMap::new();
+        let mut after_key = 0;
+
+        loop {
+            let entries = fetch_history(&mut *tx, account_id, after_key, VERIFY_PAGE_SIZE).await?;
+            let Some(last) = entries.last() else {
+                break;
+            };
+            after_key = last.key;
+
+            for entry in &entries {
+                let prev_hash = heads.get(&entry.stack_uuid);
+                match (&entry.entry_hash, prev_hash) {
+                    (None, None) => continue,
+                    (None, Some(_)) => return Ok(Some(ChainBreak::Entry { key: entry.key })),
+                    (Some(stored), prev_hash) => {
+                        let hash = entry.chained().hash(prev_hash.unwrap_or(&GENESIS_HASH));
+                        if stored.as_slice() != hash {
+                            return Ok(Some(ChainBreak::Entry { key: entry.key }));
+                        }
+                        heads.insert(entry.stack_uuid, hash);
+                    },
+                }
+            }
+        }
+
+        let latest_heads: HashMap<StackUuid, Vec<u8>> = fetch_chain_heads(&mut *tx, account_id).await?
+            .into_iter()
+            .collect();
+
+        for (stack_uuid, head) in &latest_heads {
+            if heads.get(stack_uuid).map(|hash| hash.as_slice()) != Some(head.as_slice()) {
+                return Ok(Some(ChainBreak::Head { stack_uuid: *stack_uuid }));
+            }
+        }
+
+        if let Some(stack_uuid) = heads.keys().find(|stack_uuid| !latest_heads.contains_key(stack_uuid)) {
+            return Ok(Some(ChainBreak::Head { stack_uuid: *stack_uuid }));
+        }
+
+        if !matches_commitment(&mut tx, account_id).await? {
+            return Ok(Some(ChainBreak::Commitment));
+        }
+
+        Ok(None)
+    }
+
+    /// Hash of the newest entry of every chained stack of the account
+    pub async fn get_chain_heads(&self, account_id: &AccountId) -> Result<Vec<(StackUuid, Vec<u8>)>, sqlx::Error> {
+        fetch_chain_heads(self.read_pool(Some(account_id)).await, account_id).await
+    }
+
+}
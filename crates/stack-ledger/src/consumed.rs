@@ -0,0 +1,172 @@
+use sqlx::{Transaction, Postgres};
+use sqlx::types::Json;
+use twox_hash::XxHash3_128;
+use crate::error_code::is_consumed_violation;
+use crate::{Error, Reason, StackLedger, StackUuid};
+
+const REGION_DOMAIN: &[u8] = b"clusterium.consumed_region";
+
+/// Side of the cube of blocks sharing a `consumed_regions` row
+const REGION_SIZE: i128 = 16;
+const REGION_BYTES: i32 = (REGION_SIZE * REGION_SIZE * REGION_SIZE / 8) as i32;
+
+/// Row of the `consumed` region holding the block and the block's bit in it, out of
+/// `16 * 16 * 16`
+pub fn compute_region_bit(x: i128, y: i128, z: i128, a: u32) -> ([u8; 16], i64) {
+    let mut bytes = Vec::with_capacity(REGION_DOMAIN.len() + 52);
+    bytes.extend_from_slice(REGION_DOMAIN);
+    bytes.extend_from_slice(&x.div_euclid(REGION_SIZE).to_le_bytes());
+    bytes.extend_from_slice(&y.div_euclid(REGION_SIZE).to_le_bytes());
+    bytes.extend_from_slice(&z.div_euclid(REGION_SIZE).to_le_bytes());
+    bytes.extend_from_slice(&a.to_le_bytes());
+
+    let bit = (x.rem_euclid(REGION_SIZE) * REGION_SIZE + y.rem_euclid(REGION_SIZE)) * REGION_SIZE + z.rem_euclid(REGION_SIZE);
+    (XxHash3_128::oneshot(&bytes).to_le_bytes(), bit as i64)
+}
+
+impl StackLedger {
+
+    /// Fails with `Error::AlreadyConsumed` if the stack uuid was created before. Loot is tracked
+    /// by coordinate in `consumed_regions`, every other stack by uuid in `consumed`
+    pub(crate) async fn consume(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, reason: Option<&Reason>) -> Result<(), Error> {
+
+        let Some(&Reason::Loot { x, y, z, a }) = reason else {
+            sqlx::query!(
+                r#"INSERT INTO consumed (stack_uuid)
+                VALUES ($1);"#,
+                stack_uuid as StackUuid)
+                .execute(&mut **tx)
+                .await
+                .map_err(|e| if is_consumed_violation(&e) { Error::AlreadyConsumed { stack_uuid } } else { Error::Sqlx(e) })?;
+            return Ok(());
+        };
+
+        let (region_key, bit) = compute_region_bit(x, y, z, a);
+
+        // The region row stays locked until commit, a concurrent loot of the same region waits
+        // and then sees the bit. Loot from before the regions existed stays in consumed until
+        // compact_consumed_batch moves it
+        let consumed = sqlx::query!(
+            r#"WITH marked AS (
+                INSERT INTO consumed_regions (region_key, bits)
+                VALUES ($1, set_bit(decode(repeat('00', $3), 'hex'), $2, 1))
+                ON CONFLICT (region_key) DO UPDATE
+                SET bits = set_bit(consumed_regions.bits, $2, 1)
+                WHERE get_bit(consumed_regions.bits, $2) = 0
+                RETURNING 1
+            )
+            SELECT EXISTS (SELECT 1 FROM marked) AS "marked!",
+            CASE WHEN EXISTS (SELECT 1 FROM consumed_compacted) THEN FALSE
+                ELSE EXISTS (SELECT 1 FROM consumed WHERE stack_uuid = $4)
+            END AS "legacy!";"#,
+            region_key.as_slice(),
+            bit,
+            REGION_BYTES,
+            stack_uuid as StackUuid)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        if !consumed.marked || consumed.legacy {
+            return Err(Error::AlreadyConsumed { stack_uuid });
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn is_consumed(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, reason: Option<&Reason>) -> Result<bool, Error> {
+
+        let Some(&Reason::Loot { x, y, z, a }) = reason else {
+            return Self::is_consumed_uuid(tx, stack_uuid).await;
+        };
+
+        let (region_key, bit) = compute_region_bit(x, y, z, a);
+        let looted = sqlx::query_scalar!(
+            r#"SELECT COALESCE((SELECT get_bit(bits, $2) = 1 FROM consumed_regions WHERE region_key = $1), FALSE)
+            OR CASE WHEN EXISTS (SELECT 1 FROM consumed_compacted) THEN FALSE
+                ELSE EXISTS (SELECT 1 FROM consumed WHERE stack_uuid = $3)
+            END AS "looted!";"#,
+            region_key.as_slice(),
+            bit,
+            stack_uuid as StackUuid)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(looted)
+    }
+
+    async fn is_consumed_uuid(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid) -> Result<bool, Error> {
+
+        let consumed = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM consumed WHERE stack_uuid = $1) AS "consumed!";"#,
+            stack_uuid as StackUuid)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(consumed)
+    }
+
+    /// Moves up to `batch_size` looted stacks from `consumed` to their region bit. Can run while
+    /// the server is live, call it until it returns 0. Once none are left loot stops being looked
+    /// up in `consumed`, so only run it after every server writes loot to the regions
+    pub async fn compact_consumed_batch(&self, batch_size: i64) -> Result<u64, Error> {
+
+        let mut tx = self.backend.begin().await?;
+
+        let rows = sqlx::query!(
+            r#"SELECT consumed.stack_uuid AS "stack_uuid: StackUuid", ledger_history.reason AS "reason!: Json<Reason>"
+            FROM consumed
+            JOIN ledger_history ON ledger_history.stack_uuid = consumed.stack_uuid AND ledger_history.operation = 'create'
+            WHERE ledger_history.reason ? 'loot'
+            LIMIT $1
+            FOR UPDATE OF consumed SKIP LOCKED;"#,
+            batch_size)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for row in &rows {
+
+            let Reason::Loot { x, y, z, a } = row.reason.0 else {
+                continue;
+            };
+            let (region_key, bit) = compute_region_bit(x, y, z, a);
+
+            sqlx::query!(
+                r#"INSERT INTO consumed_regions (region_key, bits)
+                VALUES ($1, set_bit(decode(repeat('00', $3), 'hex'), $2, 1))
+                ON CONFLICT (region_key) DO UPDATE
+                SET bits = set_bit(consumed_regions.bits, $2, 1);"#,
+                region_key.as_slice(),
+                bit,
+                REGION_BYTES)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query!(
+                r#"DELETE FROM consumed
+                WHERE stack_uuid = $1;"#,
+                row.stack_uuid as StackUuid)
+                .execute(&mut *tx)
+                .await?;
+
+        }
+
+        // Skipped rows are still there, only the last batch finds none at all
+        if rows.is_empty() {
+            sqlx::query!(
+                r#"INSERT INTO consumed_compacted
+                SELECT
+                WHERE NOT EXISTS (SELECT 1 FROM consumed_compacted)
+                AND NOT EXISTS (
+                    SELECT 1 FROM consumed
+                    JOIN ledger_history ON ledger_history.stack_uuid = consumed.stack_uuid AND ledger_history.operation = 'create'
+                    WHERE ledger_history.reason ? 'loot'
+                );"#)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(rows.len() as u64)
+    }
+
+}
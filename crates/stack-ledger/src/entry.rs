@@ -0,0 +1,107 @@
+use serde::{Serialize, Deserialize};
+use sqlx::types::Json;
+use crate::{AccountId, StackUuid};
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "ledger_operation", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Create,
+    Destroy,
+    SplitOut, // debit side of a split
+    SplitIn, // credit side of a split
+}
+
+/// Why an entry was written, stored as JSONB so support can query it directly
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    Recipe {
+        recipe_id: i32,
+        nonce: u64, // part of the crafted stack uuid
+    },
+    Trade {
+        trade_id: String,
+    },
+    AdminTicket {
+        ticket: String,
+    },
+    Loot {
+        x: i128,
+        y: i128,
+        z: i128,
+        a: u32,
+    },
+    Seed {
+        seed: String,
+    },
+    Migration {
+        migration_id: String,
+        stack_uuid: StackUuid, // the converted stack
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct LedgerEntry {
+    pub(crate) key: i64,
+    pub(crate) account_id: AccountId,
+    pub(crate) stack_uuid: StackUuid,
+    pub(crate) sequence_number: i32,
+    pub(crate) qty: i64,
+    pub(crate) balance: i64,
+    pub(crate) item_type: i32,
+    pub(crate) operation: Operation,
+    pub(crate) reason: Option<Json<Reason>>,
+    pub(crate) entry_hash: Option<Vec<u8>>,
+    pub(crate) created_at: i64,
+}
+
+impl LedgerEntry {
+
+    pub fn get_key(&self) -> i64 {
+        self.key
+    }
+
+    pub fn get_account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    pub fn get_uuid(&self) -> StackUuid {
+        self.stack_uuid
+    }
+
+    pub fn get_sequence_number(&self) -> i32 {
+        self.sequence_number
+    }
+
+    pub fn get_qty(&self) -> i64 {
+        self.qty
+    }
+
+    pub fn get_balance(&self) -> i64 {
+        self.balance
+    }
+
+    pub fn get_type(&self) -> i32 {
+        self.item_type
+    }
+
+    pub fn get_operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn get_reason(&self) -> Option<&Reason> {
+        self.reason.as_ref().map(|reason| &reason.0)
+    }
+
+    /// None for entries written before the ledger was hash chained
+    pub fn get_entry_hash(&self) -> Option<&[u8]> {
+        self.entry_hash.as_deref()
+    }
+
+    /// Unix timestamp
+    pub fn get_created_at(&self) -> i64 {
+        self.created_at
+    }
+
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{Postgres, Transaction};
+use sqlx::types::Json;
+use crate::chain::{ChainedEntry, GENESIS_HASH};
+use crate::freeze::compute_account_lock_key;
+use crate::keys::{compute_composite_key_bytes, compute_latest_key, CURRENT_KEY_VERSION};
+use crate::merkle::{compute_leaf_hash, move_tree};
+use crate::{AccountId, Error, LedgerEntry, Reason, StackLedger, StackUuid};
+
+// Set once per process, see StackLedger::set_erasure_key
+static ERASURE_KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+// Keyed, so a leaked table can't be matched against a list of known ids
+fn compute_account_hash(key: &[u8], account_id: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(account_id.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+async fn lock_account(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId) -> Result<(), sqlx::Error> {
+    // Exclusive, waits for every debit and credit of this account that is still running
+    sqlx::query!(
+        r#"SELECT 1 AS locked FROM pg_advisory_xact_lock($1);"#,
+        compute_account_lock_key(account_id.as_str()))
+        .fetch_one(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+impl StackLedger {
+
+    /// Key erased accounts are recorded under, it has to stay secret and be the same on every
+    /// server writing to the ledger. Set it once at startup: erasing fails without it, and so do
+    /// creates and credits once an account was erased. Fails if another key was already set
+    pub fn set_erasure_key(key: &[u8]) -> Result<(), Error> {
+        if ERASURE_KEY.get_or_init(|| key.to_vec()) != key {
+            return Err(Error::ErasureKeyAlreadySet);
+        }
+        Ok(())
+    }
+
+    /// Replaces the account id with a random pseudonym ("erased-" and 32 hex characters) in every
+    /// table, archived entries included, recomputing the keys, hash chains and Merkle leaves derived from it. Balances and
+    /// the entries of other accounts don't change. Its briefcases get the pseudonym with their
+    /// suffix. Returns the pseudonym. Stacks can't be created for or sent to the old id afterwards
+    pub async fn erase_account(&self, account_id: &AccountId) -> Result<AccountId, Error> {
+
+        let key = ERASURE_KEY.get().ok_or(Error::MissingErasureKey)?;
+        let mut tx = self.backend.begin().await?;
+        lock_account(&mut tx, account_id).await?;
+
+        let pseudonym = sqlx::query_scalar!(
+            r#"SELECT 'erased-' || replace(gen_random_uuid()::TEXT, '-', '') AS "pseudonym!";"#)
+            .fetch_one(&mut *tx)
+            .await?;
+        let pseudonym = AccountId::new(&pseudonym)?;
+
+        let briefcases = sqlx::query_scalar!(
+            r#"SELECT account_id AS "account_id: AccountId" FROM inventories
+            WHERE starts_with(account_id, $1 || '_b')
+            ORDER BY account_id;"#,
+            account_id as &AccountId)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let mut renames = vec![(account_id.clone(), pseudonym.clone())];
+        for briefcase in briefcases {
+            let Some(n) = briefcase.get_briefcase_number() else {
+                continue;
+            };
+            if briefcase.get_owner() != account_id.as_str() {
+                continue;
+            }
+            lock_account(&mut tx, &briefcase).await?;
+            renames.push((briefcase, AccountId::new_briefcase(&pseudonym, n)?));
+        }
+
+        for (old_account_id, new_account_id) in &renames {
+            Self::rename_account(&mut tx, old_account_id, new_account_id).await?;
+        }
+
+        sqlx::query!(
+            r#"INSERT INTO erased_accounts (account_hash)
+            VALUES ($1)
+            ON CONFLICT ON CONSTRAINT uq_erased_accounts_account_hash DO NOTHING;"#,
+            compute_account_hash(key, account_id.as_str()))
+            .execute(&mut *tx)
+            .await?;
+
+        let account_ids: Vec<&AccountId> = renames
+            .iter()
+            .flat_map(|(old_account_id, new_account_id)| [old_account_id, new_account_id])
+            .collect();
+        self.commit(tx, &account_ids).await?;
+        Ok(pseudonym)
+    }
+
+    /// Fails for an erased account or a briefcase of one. Takes the operation locks of both in
+    /// shared mode, so an erasure waits for the credits already in flight
+    pub(crate) async fn ensure_not_erased(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId) -> Result<(), Error> {
+
+        let owner = account_id.get_owner();
+
+        for lock_account_id in [account_id.as_str(), owner] {
+            sqlx::query!(
+                r#"SELECT 1 AS locked FROM pg_advisory_xact_lock_shared($1);"#,
+                compute_account_lock_key(lock_account_id))
+                .fetch_one(&mut **tx)
+                .await?;
+        }
+
+        let key = ERASURE_KEY.get();
+        let hashes: Vec<Vec<u8>> = key
+            .map(|key| vec![compute_account_hash(key, account_id.as_str()), compute_account_hash(key, owner)])
+            .unwrap_or_default();
+
+        let erased = sqlx::query!(
+            r#"SELECT
+                EXISTS (SELECT 1 FROM erased_accounts WHERE account_hash = ANY($1)) AS "erased!",
+                EXISTS (SELECT 1 FROM erased_accounts) AS "any_erased!";"#,
+            &hashes)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        if erased.erased {
+            return Err(Error::AccountErased {
+                account_id: account_id.clone(),
+            });
+        }
+        // Without the key there's no telling which accounts those are
+        if key.is_none() && erased.any_erased {
+            return Err(Error::MissingErasureKey);
+        }
+
+        Ok(())
+    }
+
+    async fn rename_account(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, pseudonym: &AccountId) -> Result<(), Error> {
+
+        let latest_rows = sqlx::query!(
+            r#"SELECT key, stack_uuid AS "stack_uuid: StackUuid", sequence_number, balance, item_type
+            FROM latest
+            WHERE account_id = $1
+            FOR UPDATE;"#,
+            account_id as &AccountId)
+            .fetch_all(&mut **tx)
+            .await?;
+
+        let entries = sqlx::query_as!(LedgerEntry,
+            r#"SELECT key AS "key!", account_id AS "account_id!: AccountId", stack_uuid AS "stack_uuid!: StackUuid", sequence_number AS "sequence_number!", qty AS "qty!", balance AS "balance!",
+            item_type AS "item_type!", operation AS "operation!: _", reason AS "reason: Json<Reason>", entry_hash, created_at AS "created_at!"
+            FROM ledger_history
+            WHERE account_id = $1
+            ORDER BY key;"#,
+            account_id as &AccountId)
+            .fetch_all(&mut **tx)
+            .await?;
+
+        // Same walk as verify_chain, entries written before the chain existed stay unhashed
+        let mut heads: HashMap<StackUuid, [u8; 32]> = HashMap::new();
+
+        for entry in &entries {
+
+            let entry_hash = match (&entry.entry_hash, heads.get(&entry.stack_uuid)) {
+                (None, _) => None,
+                (Some(_), prev_hash) => {
+                    let hash = ChainedEntry {
+                        account_id: pseudonym,
+                        stack_uuid: entry.stack_uuid,
+                        sequence_number: entry.sequence_number,
+                        qty: entry.qty,
+                        balance: entry.balance,
+                        item_type: entry.item_type,
+                        operation: entry.operation,
+                        reason: entry.get_reason(),
+                    }.hash(prev_hash.unwrap_or(&GENESIS_HASH));
+                    heads.insert(entry.stack_uuid, hash);
+                    Some(hash)
+                },
+            };
+            let composite_key_bytes = compute_composite_key_bytes(CURRENT_KEY_VERSION, pseudonym, entry.stack_uuid, entry.sequence_number);
+
+            let updated = sqlx::query!(
+                r#"UPDATE ledger
+                SET account_id = $1, composite = $2, key_version = $3, entry_hash = $4
+                WHERE account_id = $5 AND key = $6;"#,
+                pseudonym as &AccountId,
+                composite_key_bytes.as_slice(),
+                CURRENT_KEY_VERSION as i16,
+                entry_hash.as_ref().map(|hash| hash.as_slice()),
+                account_id as &AccountId,
+                entry.key)
+                .execute(&mut **tx)
+                .await?;
+
+            if updated.rows_affected() == 0 {
+                sqlx::query!(
+                    r#"UPDATE ledger_archive
+                    SET account_id = $1, composite = $2, key_version = $3, entry_hash = $4
+                    WHERE account_id = $5 AND key = $6;"#,
+                    pseudonym as &AccountId,
+                    composite_key_bytes.as_slice(),
+                    CURRENT_KEY_VERSION as i16,
+                    entry_hash.as_ref().map(|hash| hash.as_slice()),
+                    account_id as &AccountId,
+                    entry.key)
+                    .execute(&mut **tx)
+                    .await?;
+            }
+
+        }
+
+        for row in &latest_rows {
+
+            let new_key_bytes = compute_latest_key(CURRENT_KEY_VERSION, pseudonym, row.stack_uuid).to_le_bytes();
+            let entry_hash = heads.get(&row.stack_uuid).map(|hash| hash.as_slice());
+            let leaf_hash = compute_leaf_hash(&new_key_bytes, row.stack_uuid, row.sequence_number, row.balance, row.item_type, entry_hash.unwrap_or_default());
+
+            sqlx::query!(
+                r#"UPDATE latest
+                SET account_id = $1, key = $2, key_version = $3, entry_hash = $4, leaf_hash = $5
+                WHERE account_id = $6 AND key = $7;"#,
+                pseudonym as &AccountId,
+                new_key_bytes.as_slice(),
+                CURRENT_KEY_VERSION as i16,
+                entry_hash,
+                leaf_hash.as_slice(),
+                account_id as &AccountId,
+                row.key)
+                .execute(&mut **tx)
+                .await?;
+
+            sqlx::query!(
+                r#"UPDATE inventories
+                SET latest_keys = array_replace(latest_keys, $1, $2)
+                WHERE account_id = $3;"#,
+                row.key,
+                new_key_bytes.as_slice(),
+                account_id as &AccountId)
+                .execute(&mut **tx)
+                .await?;
+
+            sqlx::query!(
+                r#"UPDATE stacks
+                SET latest_keys = array_replace(latest_keys, $1, $2)
+                WHERE stack_uuid = $3;"#,
+                row.key,
+                new_key_bytes.as_slice(),
+                row.stack_uuid as StackUuid)
+                .execute(&mut **tx)
+                .await?;
+
+        }
+
+        move_tree(tx, account_id, pseudonym).await?;
+
+        sqlx::query!(
+            r#"UPDATE inventories
+            SET account_id = $1
+            WHERE account_id = $2;"#,
+            pseudonym as &AccountId,
+            account_id as &AccountId)
+            .execute(&mut **tx)
+            .await?;
+
+        sqlx::query!(
+            r#"UPDATE frozen_accounts
+            SET account_id = $1
+            WHERE account_id = $2;"#,
+            pseudonym as &AccountId,
+            account_id as &AccountId)
+            .execute(&mut **tx)
+            .await?;
+
+        sqlx::query!(
+            r#"UPDATE craft_inputs
+            SET account_id = $1
+            WHERE account_id = $2;"#,
+            pseudonym as &AccountId,
+            account_id as &AccountId)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
+    }
+
+}
@@ -0,0 +1,106 @@
+use crate::Error;
+
+const CONSUMED_CONSTRAINT: &str = "exc_consumed_stack_uuid";
+
+/// What a failed request tells the client. The numbers are part of the network protocol, never
+/// reuse or renumber them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum ErrorCode {
+    StackNotFound = 1,
+    AlreadyLooted = 2,
+    NotEnoughBalance = 3,
+    ItemTypeMismatch = 4,
+    AccountNotFound = 5,
+    /// Another request changed the same stacks first
+    Conflict = 6,
+    AccountFrozen = 7,
+    /// The request can never succeed as sent
+    InvalidRequest = 8,
+    /// The database or the world couldn't be reached
+    Unavailable = 9,
+    Internal = 10,
+    NotLootable = 11,
+}
+
+impl ErrorCode {
+
+    pub fn get_code(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Whether sending the same request again can succeed. After a `Conflict` the client should
+    /// read the stacks again first, a request with an expected sequence number will fail the same way
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ErrorCode::Conflict | ErrorCode::Unavailable)
+    }
+
+}
+
+impl Error {
+
+    pub fn get_code(&self) -> ErrorCode {
+        match self {
+            Error::Sqlx(error) => compute_sqlx_code(error),
+            // The chunk store couldn't be reached or kept changing under the request
+            Error::World(_) => ErrorCode::Unavailable,
+            Error::StackNotFound { .. } => ErrorCode::StackNotFound,
+            Error::AlreadyConsumed { .. } => ErrorCode::AlreadyLooted,
+            Error::NotEnoughBalance { .. } => ErrorCode::NotEnoughBalance,
+            Error::ItemTypeMismatch { .. } => ErrorCode::ItemTypeMismatch,
+            Error::AccountNotFound { .. }
+            | Error::AccountErased { .. } => ErrorCode::AccountNotFound,
+            Error::SequenceConflict { .. }
+            | Error::StaleVersion { .. }
+            | Error::BlockChanged { .. } => ErrorCode::Conflict,
+            Error::AccountFrozen { .. } => ErrorCode::AccountFrozen,
+            Error::NotLootable { .. }
+            | Error::EmptyBlock { .. } => ErrorCode::NotLootable,
+            Error::InvalidAccountId(_)
+            | Error::QuantityOutOfRange { .. }
+            | Error::BalanceOverflow { .. }
+            | Error::ConservationViolated { .. }
+            | Error::ConservationOnCraft { .. }
+            | Error::MissingSequenceNumber { .. }
+            | Error::InvalidSeed(_)
+            | Error::InvalidMigration { .. } => ErrorCode::InvalidRequest,
+            Error::TotalOverflow { .. }
+            | Error::InvalidPartitionBound { .. }
+            | Error::InvalidLootTable { .. }
+            | Error::InvalidLootTablesFile(_)
+            | Error::MissingErasureKey
+            | Error::ErasureKeyAlreadySet
+            | Error::Io(_)
+            | Error::Csv(_)
+            | Error::Parquet(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.get_code().is_retryable()
+    }
+
+}
+
+// Errors the ledger doesn't turn into a domain error itself, like two transactions crediting the
+// same new stack to an account at once
+fn compute_sqlx_code(error: &sqlx::Error) -> ErrorCode {
+    match error {
+        sqlx::Error::Database(error) => match error.code().as_deref() {
+            // unique_violation, exclusion_violation
+            Some("23505" | "23P01") => ErrorCode::Conflict,
+            // serialization_failure, deadlock_detected, lock_not_available
+            Some("40001" | "40P01" | "55P03") => ErrorCode::Conflict,
+            _ => ErrorCode::Internal,
+        },
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => ErrorCode::Unavailable,
+        _ => ErrorCode::Internal,
+    }
+}
+
+pub(crate) fn is_consumed_violation(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(error) => error.constraint() == Some(CONSUMED_CONSTRAINT),
+        _ => false,
+    }
+}
@@ -0,0 +1,422 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::TryStreamExt;
+use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use sqlx::{PgPool, Postgres, Transaction};
+use crate::{Error, StackUuid};
+
+const ROW_GROUP_SIZE: usize = 65_536;
+const HORIZON_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+/// Result of `LedgerExporter::export_ledger`, pass `get_last_key` as `after_key` to the next run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LedgerCheckpoint {
+    last_key: i64,
+    rows: u64,
+}
+
+impl LedgerCheckpoint {
+
+    pub fn get_last_key(&self) -> i64 {
+        self.last_key
+    }
+
+    pub fn get_rows(&self) -> u64 {
+        self.rows
+    }
+
+}
+
+#[derive(Clone, Copy)]
+enum ColumnType {
+    Int32,
+    Int64,
+    Text,
+    OptionalText,
+}
+
+type Columns = &'static [(&'static str, ColumnType)];
+
+const LEDGER_COLUMNS: Columns = &[
+    ("key", ColumnType::Int64),
+    ("account_id", ColumnType::Text),
+    ("stack_uuid", ColumnType::Text),
+    ("sequence_number", ColumnType::Int32),
+    ("qty", ColumnType::Int64),
+    ("balance", ColumnType::Int64),
+    ("item_type", ColumnType::Int32),
+    ("operation", ColumnType::Text),
+    ("reason", ColumnType::OptionalText),
+    ("entry_hash", ColumnType::OptionalText),
+    ("created_at", ColumnType::Int64),
+];
+
+const LATEST_COLUMNS: Columns = &[
+    ("key", ColumnType::Text),
+    ("account_id", ColumnType::Text),
+    ("stack_uuid", ColumnType::Text),
+    ("sequence_number", ColumnType::Int32),
+    ("balance", ColumnType::Int64),
+    ("item_type", ColumnType::Int32),
+    ("key_version", ColumnType::Int32),
+    ("entry_hash", ColumnType::OptionalText),
+    ("leaf_hash", ColumnType::OptionalText),
+];
+
+const INVENTORY_COLUMNS: Columns = &[
+    ("account_id", ColumnType::Text),
+    ("stack_uuid", ColumnType::Text),
+    ("sequence_number", ColumnType::Int32),
+    ("balance", ColumnType::Int64),
+    ("item_type", ColumnType::Int32),
+];
+
+enum Cell {
+    Int32(i32),
+    Int64(i64),
+    Text(Option<String>),
+}
+
+impl Cell {
+    fn to_csv(&self) -> String {
+        match self {
+            Cell::Int32(value) => value.to_string(),
+            Cell::Int64(value) => value.to_string(),
+            Cell::Text(value) => value.clone().unwrap_or_default(),
+        }
+    }
+}
+
+enum ColumnBuffer {
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Text {
+        values: Vec<ByteArray>,
+        definition_levels: Option<Vec<i16>>, // only for optional columns
+    },
+}
+
+struct ParquetTable<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    buffers: Vec<ColumnBuffer>,
+    rows: usize,
+}
+
+impl<W: Write + Send> ParquetTable<W> {
+
+    fn new(writer: W, table: &str, columns: Columns) -> Result<Self, Error> {
+        let fields: String = columns
+            .iter()
+            .map(|(name, column_type)| match column_type {
+                ColumnType::Int32 => format!("REQUIRED INT32 {name}; "),
+                ColumnType::Int64 => format!("REQUIRED INT64 {name}; "),
+                ColumnType::Text => format!("REQUIRED BYTE_ARRAY {name} (UTF8); "),
+                ColumnType::OptionalText => format!("OPTIONAL BYTE_ARRAY {name} (UTF8); "),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message {table} {{ {fields}}}"))?;
+
+        let buffers = columns
+            .iter()
+            .map(|(_, column_type)| match column_type {
+                ColumnType::Int32 => ColumnBuffer::Int32(Vec::new()),
+                ColumnType::Int64 => ColumnBuffer::Int64(Vec::new()),
+                ColumnType::Text => ColumnBuffer::Text { values: Vec::new(), definition_levels: None },
+                ColumnType::OptionalText => ColumnBuffer::Text { values: Vec::new(), definition_levels: Some(Vec::new()) },
+            })
+            .collect();
+
+        Ok(Self {
+            writer: SerializedFileWriter::new(writer, Arc::new(schema), Arc::new(WriterProperties::builder().build()))?,
+            buffers,
+            rows: 0,
+        })
+    }
+
+    fn write_row(&mut self, row: Vec<Cell>) -> Result<(), Error> {
+        for (buffer, cell) in self.buffers.iter_mut().zip(row) {
+            match (buffer, cell) {
+                (ColumnBuffer::Int32(values), Cell::Int32(value)) => values.push(value),
+                (ColumnBuffer::Int64(values), Cell::Int64(value)) => values.push(value),
+                (ColumnBuffer::Text { values, definition_levels }, Cell::Text(value)) => {
+                    if let Some(levels) = definition_levels {
+                        levels.push(value.is_some() as i16);
+                    }
+                    if let Some(value) = value {
+                        values.push(ByteArray::from(value.into_bytes()));
+                    }
+                },
+                _ => unreachable!("rows are built for the columns of their table"),
+            }
+        }
+
+        self.rows += 1;
+        if self.rows == ROW_GROUP_SIZE {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn flush_row_group(&mut self) -> Result<(), Error> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+
+        let mut row_group = self.writer.next_row_group()?;
+        let mut buffers = self.buffers.iter_mut();
+        while let Some(mut column) = row_group.next_column()? {
+            match buffers.next().expect("one buffer per column") {
+                ColumnBuffer::Int32(values) => {
+                    column.typed::<Int32Type>().write_batch(values, None, None)?;
+                    values.clear();
+                },
+                ColumnBuffer::Int64(values) => {
+                    column.typed::<Int64Type>().write_batch(values, None, None)?;
+                    values.clear();
+                },
+                ColumnBuffer::Text { values, definition_levels } => {
+                    column.typed::<ByteArrayType>().write_batch(values, definition_levels.as_deref(), None)?;
+                    values.clear();
+                    if let Some(levels) = definition_levels {
+                        levels.clear();
+                    }
+                },
+            }
+            column.close()?;
+        }
+        row_group.close()?;
+
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        self.flush_row_group()?;
+        self.writer.close()?;
+        Ok(())
+    }
+
+}
+
+enum TableWriter<W: Write + Send> {
+    Csv(csv::Writer<W>),
+    Parquet(ParquetTable<W>),
+}
+
+impl<W: Write + Send> TableWriter<W> {
+
+    fn new(writer: W, format: ExportFormat, table: &str, columns: Columns) -> Result<Self, Error> {
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(columns.iter().map(|(name, _)| *name))?;
+                Ok(TableWriter::Csv(writer))
+            },
+            ExportFormat::Parquet => Ok(TableWriter::Parquet(ParquetTable::new(writer, table, columns)?)),
+        }
+    }
+
+    fn write_row(&mut self, row: Vec<Cell>) -> Result<(), Error> {
+        match self {
+            TableWriter::Csv(writer) => writer.write_record(row.iter().map(Cell::to_csv))?,
+            TableWriter::Parquet(table) => table.write_row(row)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            TableWriter::Csv(mut writer) => writer.flush()?,
+            TableWriter::Parquet(table) => table.finish()?,
+        }
+        Ok(())
+    }
+
+}
+
+/// Streams the ledger tables into files for offline analytics. Hashes and latest keys are written
+/// as hex, stack uuids as decimal and reasons as JSON. Files are written synchronously as rows
+/// arrive, so give it a buffered writer
+pub struct LedgerExporter {
+    pool: PgPool,
+    replica: Option<PgPool>,
+}
+
+impl LedgerExporter {
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            replica: None,
+        }
+    }
+
+    /// Reads every row from `replica`, the primary is only asked for the ledger key sequence
+    pub fn with_replica(mut self, replica: PgPool) -> Self {
+        self.replica = Some(replica);
+        self
+    }
+
+    fn read_pool(&self) -> &PgPool {
+        self.replica.as_ref().unwrap_or(&self.pool)
+    }
+
+    // Keys come from a sequence, so a transaction still open can commit a key lower than one
+    // already visible. Every write to ledger first locks its latest row or marks its stack as
+    // consumed, so it has an xid before it takes a key, older than an xid taken after reading the
+    // sequence. A snapshot whose xmin is past that xid sees every key up to the sequence committed
+    // or rolled back for good. On a replica the xmin only gets there once they are replayed
+    async fn begin_at_ledger_horizon(&self) -> Result<(Transaction<'_, Postgres>, i64), Error> {
+
+        let mut marker = self.pool.begin().await?;
+        let horizon = sqlx::query_scalar!(
+            "SELECT pg_sequence_last_value(pg_get_serial_sequence('ledger', 'key')::REGCLASS);")
+            .fetch_one(&mut *marker)
+            .await?;
+        let xid = sqlx::query_scalar!(
+            r#"SELECT pg_current_xact_id()::TEXT AS "xid!";"#)
+            .fetch_one(&mut *marker)
+            .await?;
+        marker.commit().await?;
+
+        loop {
+            let mut tx = self.read_pool().begin().await?;
+            sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY;")
+                .execute(&mut *tx)
+                .await?;
+
+            let settled = sqlx::query_scalar!(
+                r#"SELECT pg_snapshot_xmin(pg_current_snapshot()) > $1::TEXT::XID8 AS "settled!";"#,
+                xid)
+                .fetch_one(&mut *tx)
+                .await?;
+            if settled {
+                return Ok((tx, horizon.unwrap_or(0)));
+            }
+
+            tx.rollback().await?;
+            tokio::time::sleep(HORIZON_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Entries with a key above `after_key` (use 0 for the first run), in key order, archived
+    /// ones included. Runs can be repeated with the returned checkpoint without missing or
+    /// duplicating entries
+    pub async fn export_ledger<W: Write + Send>(&self, writer: W, format: ExportFormat, after_key: i64) -> Result<LedgerCheckpoint, Error> {
+
+        let (mut tx, horizon) = self.begin_at_ledger_horizon().await?;
+        let mut table = TableWriter::new(writer, format, "ledger", LEDGER_COLUMNS)?;
+        let mut checkpoint = LedgerCheckpoint {
+            last_key: after_key,
+            rows: 0,
+        };
+
+        let mut rows = sqlx::query!(
+            r#"SELECT key AS "key!", account_id AS "account_id!", stack_uuid AS "stack_uuid!: StackUuid", sequence_number AS "sequence_number!", qty AS "qty!",
+            balance AS "balance!", item_type AS "item_type!", operation::TEXT AS "operation!", reason::TEXT AS reason,
+            encode(entry_hash, 'hex') AS entry_hash, created_at AS "created_at!"
+            FROM ledger_history
+            WHERE key > $1 AND key <= $2
+            ORDER BY key;"#,
+            after_key,
+            horizon)
+            .fetch(&mut *tx);
+
+        while let Some(row) = rows.try_next().await? {
+            checkpoint.last_key = row.key;
+            checkpoint.rows += 1;
+            table.write_row(vec![
+                Cell::Int64(row.key),
+                Cell::Text(Some(row.account_id)),
+                Cell::Text(Some(row.stack_uuid.to_string())),
+                Cell::Int32(row.sequence_number),
+                Cell::Int64(row.qty),
+                Cell::Int64(row.balance),
+                Cell::Int32(row.item_type),
+                Cell::Text(Some(row.operation)),
+                Cell::Text(row.reason),
+                Cell::Text(row.entry_hash),
+                Cell::Int64(row.created_at),
+            ])?;
+        }
+
+        table.finish()?;
+
+        // Nothing else can show up below the horizon
+        checkpoint.last_key = checkpoint.last_key.max(horizon);
+        Ok(checkpoint)
+    }
+
+    /// Every `latest` row, including empty stacks and rows no inventory points at anymore.
+    /// Returns the number of rows written
+    pub async fn export_latest<W: Write + Send>(&self, writer: W, format: ExportFormat) -> Result<u64, Error> {
+
+        let mut table = TableWriter::new(writer, format, "latest", LATEST_COLUMNS)?;
+        let mut count = 0;
+
+        let mut rows = sqlx::query!(
+            r#"SELECT encode(key, 'hex') AS "key!", account_id, stack_uuid AS "stack_uuid: StackUuid", sequence_number, balance, item_type,
+            key_version, encode(entry_hash, 'hex') AS entry_hash, encode(leaf_hash, 'hex') AS leaf_hash
+            FROM latest;"#)
+            .fetch(self.read_pool());
+
+        while let Some(row) = rows.try_next().await? {
+            count += 1;
+            table.write_row(vec![
+                Cell::Text(Some(row.key)),
+                Cell::Text(Some(row.account_id)),
+                Cell::Text(Some(row.stack_uuid.to_string())),
+                Cell::Int32(row.sequence_number),
+                Cell::Int64(row.balance),
+                Cell::Int32(row.item_type),
+                Cell::Int32(row.key_version.into()),
+                Cell::Text(row.entry_hash),
+                Cell::Text(row.leaf_hash),
+            ])?;
+        }
+
+        table.finish()?;
+        Ok(count)
+    }
+
+    /// The stacks every inventory holds, what `InventoryManager::get_inventory` returns for each
+    /// account. Returns the number of rows written
+    pub async fn export_inventories<W: Write + Send>(&self, writer: W, format: ExportFormat) -> Result<u64, Error> {
+
+        let mut table = TableWriter::new(writer, format, "inventories", INVENTORY_COLUMNS)?;
+        let mut count = 0;
+
+        let mut rows = sqlx::query!(
+            r#"SELECT latest.account_id, latest.stack_uuid AS "stack_uuid: StackUuid", latest.sequence_number, latest.balance, latest.item_type
+            FROM latest
+            JOIN inventories ON inventories.account_id = latest.account_id
+            WHERE latest.key = ANY(inventories.latest_keys)
+            ORDER BY latest.account_id, latest.stack_uuid;"#)
+            .fetch(self.read_pool());
+
+        while let Some(row) = rows.try_next().await? {
+            count += 1;
+            table.write_row(vec![
+                Cell::Text(Some(row.account_id)),
+                Cell::Text(Some(row.stack_uuid.to_string())),
+                Cell::Int32(row.sequence_number),
+                Cell::Int64(row.balance),
+                Cell::Int32(row.item_type),
+            ])?;
+        }
+
+        table.finish()?;
+        Ok(count)
+    }
+
+}
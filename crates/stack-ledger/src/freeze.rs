@@ -0,0 +1,104 @@
+use sqlx::{PgPool, Transaction, Postgres};
+use twox_hash::XxHash3_64;
+use crate::{AccountId, Error, LedgerBackend, StackLedger};
+
+pub(crate) fn compute_account_lock_key(account_id: &str) -> i64 {
+    XxHash3_64::oneshot(account_id.as_bytes()) as i64
+}
+
+impl StackLedger {
+
+    /// Takes the account's operation lock in shared mode, so debits on the same account don't
+    /// block each other, but a freeze waits for the ones already in flight
+    pub(crate) async fn ensure_not_frozen(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId) -> Result<(), Error> {
+
+        // Freezing an account freezes its briefcases too
+        let owner = account_id.get_owner();
+
+        for lock_account_id in [account_id.as_str(), owner] {
+            sqlx::query!(
+                r#"SELECT 1 AS locked FROM pg_advisory_xact_lock_shared($1);"#,
+                compute_account_lock_key(lock_account_id))
+                .fetch_one(&mut **tx)
+                .await?;
+        }
+
+        let frozen = sqlx::query!(
+            r#"SELECT reason, expires_at FROM frozen_accounts
+            WHERE account_id = ANY($1)
+            AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM now())::BIGINT)
+            LIMIT 1;"#,
+            &[account_id.as_str(), owner] as &[&str])
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        if let Some(frozen) = frozen {
+            return Err(Error::AccountFrozen {
+                account_id: account_id.clone(),
+                reason: frozen.reason,
+                expires_at: frozen.expires_at,
+            });
+        }
+
+        Ok(())
+    }
+
+}
+
+impl<B: LedgerBackend> StackLedger<B> {
+
+    /// `expires_at` is a unix timestamp, `None` keeps the account frozen until `unfreeze_account`
+    pub async fn freeze_account(&self, account_id: &AccountId, reason: &str, expires_at: Option<i64>) -> Result<(), Error> {
+        self.backend.freeze_account(account_id, reason, expires_at).await
+    }
+
+    pub async fn unfreeze_account(&self, account_id: &AccountId) -> Result<(), Error> {
+        self.backend.unfreeze_account(account_id).await
+    }
+
+}
+
+pub(crate) async fn freeze_account(pool: &PgPool, account_id: &AccountId, reason: &str, expires_at: Option<i64>) -> Result<(), Error> {
+
+    let lock_key = compute_account_lock_key(account_id.as_str());
+    let mut tx = pool.begin().await?;
+
+    // Exclusive, waits for every debit of this account that is still running
+    sqlx::query!(
+        r#"SELECT 1 AS locked FROM pg_advisory_xact_lock($1);"#,
+        lock_key)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    // Exclusion constraints don't support ON CONFLICT DO UPDATE
+    sqlx::query!(
+        r#"DELETE FROM frozen_accounts
+        WHERE account_id = $1;"#,
+        account_id as &AccountId)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!(
+        r#"INSERT INTO frozen_accounts (account_id, reason, expires_at)
+        VALUES ($1, $2, $3);"#,
+        account_id as &AccountId,
+        reason,
+        expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub(crate) async fn unfreeze_account(pool: &PgPool, account_id: &AccountId) -> Result<(), Error> {
+
+    sqlx::query!(
+        r#"DELETE FROM frozen_accounts
+        WHERE account_id = $1;"#,
+        account_id as &AccountId)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use sqlx::PgExecutor;
+use sqlx::types::Json;
+use crate::{AccountId, InventoryManager, LedgerEntry, Reason, StackUuid};
+
+pub(crate) async fn fetch_history<'e, E: PgExecutor<'e>>(executor: E, account_id: &AccountId, after_key: i64, limit: i64) -> Result<Vec<LedgerEntry>, sqlx::Error> {
+
+    sqlx::query_as!(LedgerEntry,
+        r#"SELECT key AS "key!", account_id AS "account_id!: AccountId", stack_uuid AS "stack_uuid!: StackUuid", sequence_number AS "sequence_number!", qty AS "qty!", balance AS "balance!",
+        item_type AS "item_type!", operation AS "operation!: _", reason AS "reason: Json<Reason>", entry_hash, created_at AS "created_at!"
+        FROM ledger_history
+        WHERE account_id = $1 AND key > $2
+        ORDER BY key
+        LIMIT $3;"#,
+        account_id as &AccountId,
+        after_key,
+        limit)
+        .fetch_all(executor)
+        .await
+}
+
+impl InventoryManager {
+
+    /// Entries of an account in the order they were written, starting after `after_key` (use 0
+    /// for the first page)
+    pub async fn get_history(&self, account_id: &AccountId, after_key: i64, limit: i64) -> Result<Vec<LedgerEntry>, sqlx::Error> {
+        fetch_history(self.read_pool(Some(account_id)).await, account_id, after_key, limit).await
+    }
+
+    /// Every entry of a stack across all the accounts that held it. Isn't filtered by account, so
+    /// it reads every partition of `ledger` and the archive, keep it to audits
+    pub async fn get_lineage(&self, stack_uuid: StackUuid) -> Result<Vec<LedgerEntry>, sqlx::Error> {
+
+        sqlx::query_as!(LedgerEntry,
+            r#"SELECT key AS "key!", account_id AS "account_id!: AccountId", stack_uuid AS "stack_uuid!: StackUuid", sequence_number AS "sequence_number!", qty AS "qty!", balance AS "balance!",
+            item_type AS "item_type!", operation AS "operation!: _", reason AS "reason: Json<Reason>", entry_hash, created_at AS "created_at!"
+            FROM ledger_history
+            WHERE stack_uuid = $1
+            ORDER BY key;"#,
+            stack_uuid as StackUuid)
+            .fetch_all(self.read_pool(None).await)
+            .await
+    }
+
+    /// Every entry written for the same reason, e.g. both sides of a trade. Like `get_lineage` it
+    /// reads every partition
+    pub async fn get_entries_by_reason(&self, reason: &Reason) -> Result<Vec<LedgerEntry>, sqlx::Error> {
+
+        sqlx::query_as!(LedgerEntry,
+            r#"SELECT key AS "key!", account_id AS "account_id!: AccountId", stack_uuid AS "stack_uuid!: StackUuid", sequence_number AS "sequence_number!", qty AS "qty!", balance AS "balance!",
+            item_type AS "item_type!", operation AS "operation!: _", reason AS "reason: Json<Reason>", entry_hash, created_at AS "created_at!"
+            FROM ledger_history
+            WHERE reason @> $1
+            ORDER BY key;"#,
+            Json(reason) as _)
+            .fetch_all(self.read_pool(None).await)
+            .await
+    }
+
+}
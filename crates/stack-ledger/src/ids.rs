@@ -0,0 +1,216 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{Decode, Encode, Postgres, Type};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+use thiserror::{Error as ThisError};
+
+const MAX_ACCOUNT_ID_LEN: usize = 64;
+/// "_b" and the digits of any u32, so every account has room for its briefcases
+const BRIEFCASE_SUFFIX_LEN: usize = 12;
+const MAX_OWNER_LEN: usize = MAX_ACCOUNT_ID_LEN - BRIEFCASE_SUFFIX_LEN;
+
+#[derive(Debug, ThisError)]
+#[error("Invalid account id '{0}': expected 1 to 52 ascii alphanumeric, '_' or '-' characters not ending in '_b{{n}}', and an optional '_b{{n}}' briefcase suffix")]
+pub struct InvalidAccountId(pub String);
+
+// Briefcases ("{account_id}_b{n}") belong to the account they are named after, n is a u32
+// written without leading zeros
+fn split_briefcase(account_id: &str) -> Option<(&str, u32)> {
+    let (owner, n) = account_id.rsplit_once("_b")?;
+    let number: u32 = n.parse().ok()?;
+    (!owner.is_empty() && number.to_string() == n).then_some((owner, number))
+}
+
+fn get_owner(account_id: &str) -> &str {
+    split_briefcase(account_id).map_or(account_id, |(owner, _)| owner)
+}
+
+/// Owner of a stack: a player, a world inventory (e.g. "xj9wka") or a briefcase ("{account_id}_b{n}")
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct AccountId(String);
+
+impl AccountId {
+
+    /// A player or world account. Every id ending in "_b{n}" is a briefcase, so those are
+    /// rejected here and only built by `new_briefcase`, or parsed back from a string
+    pub fn new(account_id: &str) -> Result<Self, InvalidAccountId> {
+        let valid_len = !account_id.is_empty() && account_id.len() <= MAX_OWNER_LEN;
+        let valid_chars = account_id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-');
+
+        if !valid_len || !valid_chars || split_briefcase(account_id).is_some() {
+            return Err(InvalidAccountId(account_id.to_string()));
+        }
+
+        Ok(Self(account_id.to_string()))
+    }
+
+    /// Briefcase `n` of `owner`, which can't be a briefcase itself
+    pub fn new_briefcase(owner: &AccountId, n: u32) -> Result<Self, InvalidAccountId> {
+        let account_id = format!("{owner}_b{n}");
+        if owner.is_briefcase() || owner.0.len() > MAX_OWNER_LEN {
+            return Err(InvalidAccountId(account_id));
+        }
+
+        Ok(Self(account_id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    /// The account itself, or the one a briefcase belongs to
+    pub fn get_owner(&self) -> &str {
+        get_owner(&self.0)
+    }
+
+    /// The n of a "{owner}_b{n}" briefcase
+    pub fn get_briefcase_number(&self) -> Option<u32> {
+        split_briefcase(&self.0).map(|(_, n)| n)
+    }
+
+    pub fn is_briefcase(&self) -> bool {
+        self.get_briefcase_number().is_some()
+    }
+
+}
+
+impl std::fmt::Display for AccountId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for AccountId {
+    type Err = InvalidAccountId;
+
+    // Either kind of account
+    fn from_str(account_id: &str) -> Result<Self, Self::Err> {
+        match split_briefcase(account_id) {
+            Some((owner, n)) => Self::new_briefcase(&Self::new(owner)?, n),
+            None => Self::new(account_id),
+        }
+    }
+}
+
+impl TryFrom<String> for AccountId {
+    type Error = InvalidAccountId;
+
+    fn try_from(account_id: String) -> Result<Self, Self::Error> {
+        account_id.parse()
+    }
+}
+
+impl From<AccountId> for String {
+    fn from(account_id: AccountId) -> Self {
+        account_id.0
+    }
+}
+
+impl Type<Postgres> for AccountId {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl PgHasArrayType for AccountId {
+    fn array_type_info() -> PgTypeInfo {
+        <String as PgHasArrayType>::array_type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for AccountId {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <&str as Encode<Postgres>>::encode(self.as_str(), buf)
+    }
+}
+
+// Not validated, stored ids may have been written under older rules and must still be readable
+impl<'r> Decode<'r, Postgres> for AccountId {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let account_id = <&str as Decode<Postgres>>::decode(value)?;
+        Ok(Self(account_id.to_string()))
+    }
+}
+
+/// Identity of a stack, stored as 16 little endian bytes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StackUuid(u128);
+
+impl StackUuid {
+
+    pub fn new(stack_uuid: u128) -> Self {
+        Self(stack_uuid)
+    }
+
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; 16] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 16]) -> Self {
+        Self(u128::from_le_bytes(bytes))
+    }
+
+}
+
+impl std::fmt::Display for StackUuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<u128> for StackUuid {
+    fn from(stack_uuid: u128) -> Self {
+        Self(stack_uuid)
+    }
+}
+
+impl From<StackUuid> for u128 {
+    fn from(stack_uuid: StackUuid) -> Self {
+        stack_uuid.0
+    }
+}
+
+impl Type<Postgres> for StackUuid {
+    fn type_info() -> PgTypeInfo {
+        <[u8; 16] as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <[u8; 16] as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl PgHasArrayType for StackUuid {
+    fn array_type_info() -> PgTypeInfo {
+        <[u8; 16] as PgHasArrayType>::array_type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for StackUuid {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <[u8; 16] as Encode<Postgres>>::encode(self.to_le_bytes(), buf)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for StackUuid {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let bytes = <[u8; 16] as Decode<Postgres>>::decode(value)?;
+        Ok(Self::from_le_bytes(bytes))
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{AccountId, InventoryManager, Stack, StackUuid};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ItemTotal {
+    item_type: i32,
+    total: i64,
+}
+
+impl ItemTotal {
+
+    pub fn get_type(&self) -> i32 {
+        self.item_type
+    }
+
+    pub fn get_total(&self) -> i64 {
+        self.total
+    }
+
+}
+
+// Every query only sees the stacks listed in the account's inventories row, like get_inventory
+
+impl InventoryManager {
+
+    pub async fn get_stack(&self, account_id: &AccountId, stack_uuid: StackUuid) -> Result<Option<Stack>, sqlx::Error> {
+
+        sqlx::query_as!(Stack,
+            r#"SELECT latest.stack_uuid AS "stack_uuid: StackUuid", latest.sequence_number, latest.balance, latest.item_type
+            FROM latest
+            JOIN inventories ON inventories.account_id = latest.account_id
+            WHERE latest.account_id = $1 AND latest.stack_uuid = $2 AND latest.key = ANY(inventories.latest_keys);"#,
+            account_id as &AccountId,
+            stack_uuid as StackUuid)
+            .fetch_optional(self.read_pool(Some(account_id)).await)
+            .await
+    }
+
+    pub async fn get_stacks_by_type(&self, account_id: &AccountId, item_type: i32) -> Result<Vec<Stack>, sqlx::Error> {
+
+        sqlx::query_as!(Stack,
+            r#"SELECT latest.stack_uuid AS "stack_uuid: StackUuid", latest.sequence_number, latest.balance, latest.item_type
+            FROM latest
+            JOIN inventories ON inventories.account_id = latest.account_id
+            WHERE latest.account_id = $1 AND latest.item_type = $2 AND latest.key = ANY(inventories.latest_keys)
+            ORDER BY latest.stack_uuid;"#,
+            account_id as &AccountId,
+            item_type)
+            .fetch_all(self.read_pool(Some(account_id)).await)
+            .await
+    }
+
+    /// Stacks in a stable order, starting after the stack `after` (use None for the first page)
+    pub async fn get_stacks_page(&self, account_id: &AccountId, after: Option<StackUuid>, limit: i64) -> Result<Vec<Stack>, sqlx::Error> {
+
+        sqlx::query_as!(Stack,
+            r#"SELECT latest.stack_uuid AS "stack_uuid: StackUuid", latest.sequence_number, latest.balance, latest.item_type
+            FROM latest
+            JOIN inventories ON inventories.account_id = latest.account_id
+            WHERE latest.account_id = $1 AND latest.key = ANY(inventories.latest_keys)
+            AND ($2::BYTEA IS NULL OR latest.stack_uuid > $2)
+            ORDER BY latest.stack_uuid
+            LIMIT $3;"#,
+            account_id as &AccountId,
+            after as Option<StackUuid>,
+            limit)
+            .fetch_all(self.read_pool(Some(account_id)).await)
+            .await
+    }
+
+    /// Summed balance per item type
+    pub async fn get_item_totals(&self, account_id: &AccountId) -> Result<Vec<ItemTotal>, sqlx::Error> {
+
+        sqlx::query_as!(ItemTotal,
+            r#"SELECT latest.item_type, SUM(latest.balance)::BIGINT AS "total!"
+            FROM latest
+            JOIN inventories ON inventories.account_id = latest.account_id
+            WHERE latest.account_id = $1 AND latest.key = ANY(inventories.latest_keys)
+            GROUP BY latest.item_type
+            ORDER BY latest.item_type;"#,
+            account_id as &AccountId)
+            .fetch_all(self.read_pool(Some(account_id)).await)
+            .await
+    }
+
+}
@@ -0,0 +1,143 @@
+use crate::keys::KeyInput;
+use crate::{AccountId, Error, Reason, StackLedger, StackUuid};
+
+const MIGRATION_DOMAIN: &[u8] = b"clusterium.item_migration";
+
+/// Converts every stack of `from_type` into a new stack of `to_type`, see
+/// `StackLedger::migrate_item_type_batch`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemMigration {
+    migration_id: String,
+    from_type: i32,
+    to_type: i32,
+    numerator: u64,
+    denominator: u64,
+}
+
+impl ItemMigration {
+
+    /// Converts one to one, use `with_ratio` to change it
+    pub fn new(migration_id: &str, from_type: i32, to_type: i32) -> Self {
+        Self {
+            migration_id: migration_id.to_string(),
+            from_type,
+            to_type,
+            numerator: 1,
+            denominator: 1,
+        }
+    }
+
+    /// `numerator` new items for every `denominator` old ones, rounded down per stack
+    pub fn with_ratio(mut self, numerator: u64, denominator: u64) -> Self {
+        self.numerator = numerator;
+        self.denominator = denominator;
+        self
+    }
+
+    pub fn get_migration_id(&self) -> &str {
+        &self.migration_id
+    }
+
+    pub fn get_from_type(&self) -> i32 {
+        self.from_type
+    }
+
+    pub fn get_to_type(&self) -> i32 {
+        self.to_type
+    }
+
+    pub fn get_ratio(&self) -> (u64, u64) {
+        (self.numerator, self.denominator)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let reason = if self.denominator == 0 {
+            "the ratio denominator is 0"
+        } else if self.from_type == self.to_type {
+            "the item type doesn't change"
+        } else {
+            return Ok(());
+        };
+
+        Err(Error::InvalidMigration {
+            migration_id: self.migration_id.clone(),
+            reason,
+        })
+    }
+
+    fn convert(&self, balance: i64) -> u64 {
+        let qty = balance as u128 * self.numerator as u128 / self.denominator as u128;
+        u64::try_from(qty).unwrap_or(u64::MAX)
+    }
+
+}
+
+// The sequence number is the one of the destroyed version, so a stack that is emptied, credited
+// again and migrated a second time gets a different uuid
+fn compute_migrated_uuid(migration_id: &str, account_id: &AccountId, stack_uuid: StackUuid, sequence_number: i32) -> StackUuid {
+    let key = KeyInput::with_domain(MIGRATION_DOMAIN)
+        .field(migration_id.as_bytes())
+        .field(account_id.as_bytes())
+        .field(&stack_uuid.to_le_bytes())
+        .field(&sequence_number.to_le_bytes())
+        .hash();
+
+    StackUuid::new(key)
+}
+
+impl StackLedger {
+
+    /// Converts up to `batch_size` non-empty stacks of the migration's old type. Each one is
+    /// destroyed whole and a new stack of the new type is created for the same account, both
+    /// entries with a `Reason::Migration` naming the old stack. Stacks that round down to 0 are
+    /// only destroyed. Goes through frozen accounts and can run while the server is live. Returns
+    /// how many stacks are still left to convert, stacks skipped because a debit had them locked
+    /// included, call it until it returns 0
+    pub async fn migrate_item_type_batch(&self, migration: &ItemMigration, batch_size: i64) -> Result<u64, Error> {
+
+        migration.validate()?;
+        let mut tx = self.backend.begin().await?;
+
+        // Converted stacks have no balance left, so every batch only sees the remaining ones.
+        // Rows locked by a running debit are picked up by a later batch
+        let rows = sqlx::query!(
+            r#"SELECT account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number, balance
+            FROM latest
+            WHERE item_type = $1 AND balance > 0
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED;"#,
+            migration.from_type,
+            batch_size)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for row in &rows {
+
+            let reason = Reason::Migration {
+                migration_id: migration.migration_id.clone(),
+                stack_uuid: row.stack_uuid,
+            };
+
+            StackLedger::admin_destroy(&mut tx, row.stack_uuid, migration.from_type, Some(row.sequence_number), &row.account_id, row.balance as u64, Some(&reason)).await?;
+
+            let qty = migration.convert(row.balance);
+            if qty > 0 {
+                let stack_uuid = compute_migrated_uuid(&migration.migration_id, &row.account_id, row.stack_uuid, row.sequence_number);
+                StackLedger::create(&mut tx, stack_uuid, migration.to_type, None, qty, &row.account_id, Some(&reason)).await?;
+            }
+
+        }
+
+        let remaining = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "remaining!" FROM latest
+            WHERE item_type = $1 AND balance > 0;"#,
+            migration.from_type)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let account_ids: Vec<&AccountId> = rows.iter().map(|row| &row.account_id).collect();
+        self.commit(tx, &account_ids).await?;
+        Ok(remaining as u64)
+    }
+
+}
@@ -0,0 +1,98 @@
+use twox_hash::XxHash3_128;
+use crate::{AccountId, StackUuid};
+
+/// How `latest.key` and `ledger.composite` were derived, stored next to them in `key_version`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(i16)]
+pub enum KeyVersion {
+    /// Raw account bytes followed by the uuid, ambiguous for accounts of different lengths
+    V0 = 0,
+    /// Domain separated and length prefixed
+    V1 = 1,
+}
+
+pub const CURRENT_KEY_VERSION: KeyVersion = KeyVersion::V1;
+
+const LATEST_DOMAIN: &[u8] = b"clusterium.latest";
+const COMPOSITE_DOMAIN: &[u8] = b"clusterium.composite";
+
+/// Fields each prefixed with their u32 LE length, so no two inputs share the same bytes
+pub(crate) struct KeyInput {
+    bytes: Vec<u8>,
+}
+
+impl KeyInput {
+
+    fn new(domain: &[u8], version: KeyVersion) -> Self {
+        let mut input = Self {
+            bytes: Vec::with_capacity(64),
+        };
+        input.bytes.extend_from_slice(&(version as i16).to_le_bytes());
+        input.field(domain)
+    }
+
+    /// Without a key version in front, for derivations that never had a legacy one
+    pub(crate) fn with_domain(domain: &[u8]) -> Self {
+        Self {
+            bytes: Vec::with_capacity(64),
+        }.field(domain)
+    }
+
+    pub(crate) fn field(mut self, bytes: &[u8]) -> Self {
+        self.bytes.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    pub(crate) fn hash(&self) -> u128 {
+        XxHash3_128::oneshot(&self.bytes)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+}
+
+pub(crate) fn compute_latest_key(version: KeyVersion, account_id: &AccountId, stack_uuid: StackUuid) -> u128 {
+    match version {
+        KeyVersion::V0 => {
+            let account_bytes = account_id.as_bytes();
+            let uuid_bytes = stack_uuid.to_le_bytes();
+            let mut bytes = Vec::with_capacity(account_bytes.len() + 16);
+            bytes.extend_from_slice(account_bytes);
+            bytes.extend_from_slice(&uuid_bytes);
+            XxHash3_128::oneshot(&bytes)
+        },
+        KeyVersion::V1 => {
+            KeyInput::new(LATEST_DOMAIN, version)
+                .field(account_id.as_bytes())
+                .field(&stack_uuid.to_le_bytes())
+                .hash()
+        },
+    }
+}
+
+pub(crate) fn compute_composite_key_bytes(version: KeyVersion, account_id: &AccountId, stack_uuid: StackUuid, sequence_number: i32) -> Vec<u8> {
+    let composite_key = match version {
+        KeyVersion::V0 => {
+            let account_bytes = account_id.as_bytes();
+            let uuid_bytes = stack_uuid.to_le_bytes();
+            let seq_num_bytes = sequence_number.to_le_bytes();
+            let mut bytes = Vec::with_capacity(account_bytes.len() + 16 + 4);
+            bytes.extend_from_slice(account_bytes);
+            bytes.extend_from_slice(&uuid_bytes);
+            bytes.extend_from_slice(&seq_num_bytes);
+            XxHash3_128::oneshot(&bytes)
+        },
+        KeyVersion::V1 => {
+            KeyInput::new(COMPOSITE_DOMAIN, version)
+                .field(account_id.as_bytes())
+                .field(&stack_uuid.to_le_bytes())
+                .field(&sequence_number.to_le_bytes())
+                .hash()
+        },
+    };
+
+    composite_key.to_le_bytes().to_vec()
+}
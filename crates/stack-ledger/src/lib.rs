@@ -1,32 +1,236 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
 use sqlx::{PgPool, Transaction, Postgres};
+use sqlx::types::Json;
 use thiserror::{Error as ThisError};
 use twox_hash::XxHash3_128;
 
+mod ids;
+mod freeze;
+mod entry;
+mod history;
+mod backend;
+mod memory;
+mod keys;
+mod rekey;
+mod partitions;
+mod cache;
+mod inventory;
+mod batch;
+mod chain;
+mod merkle;
+mod export;
+mod seed;
+mod item_migration;
+mod lineage;
+mod erasure;
+mod archive;
+mod replica;
+mod error_code;
+mod consumed;
+mod loot;
+mod world;
+mod tracker;
+
+pub use ids::{AccountId, InvalidAccountId, StackUuid};
+pub use entry::{LedgerEntry, Operation, Reason};
+pub use backend::{LedgerBackend, LedgerTx};
+pub use memory::{MemoryBackend, MemoryTx};
+pub use keys::{KeyVersion, CURRENT_KEY_VERSION};
+pub use partitions::{Partition, PartitionManager, PartitionedTable};
+pub use cache::CacheStats;
+pub use inventory::ItemTotal;
+pub use batch::{BatchReceipt, Conservation, LedgerBatch, ReceiptEntry};
+pub use chain::ChainBreak;
+pub use merkle::{compute_inventory_root, compute_leaf_hash, InclusionProof, InventoryCommitment, EMPTY_ROOT};
+pub use export::{ExportFormat, LedgerCheckpoint, LedgerExporter};
+pub use seed::{Seed, SeedReport};
+pub use item_migration::ItemMigration;
+pub use lineage::{CraftInput, LineageNode};
+pub use error_code::ErrorCode;
+pub use consumed::compute_region_bit;
+pub use loot::{LootDrop, LootTables};
+pub use world::{compute_block_index, ChunkPosition, ChunkStore, ChunkWorld, MemoryWorld, StoredChunk, WorldBlocks, COMMON_CHUNK_SIZE, RELEVANT_CHUNK_SIZE};
+pub use tracker::WriteTracker;
+
+use keys::{compute_latest_key, compute_composite_key_bytes};
+use cache::InventoryCache;
+use chain::{ChainedEntry, GENESIS_HASH};
+use merkle::{rebuild_tree, set_leaf};
+use replica::ReadReplica;
+
 #[derive(Debug, ThisError)]
 pub enum Error {
 
     #[error("Error from Sqlx: {0}")]
     Sqlx(#[from] sqlx::Error),
 
+    #[error(transparent)]
+    InvalidAccountId(#[from] InvalidAccountId),
+
     #[error("Item type mismatch for stack '{stack_uuid}': expected {expected}, got {actual}")]
     ItemTypeMismatch {
-        account_id: String,
-        stack_uuid: u128,
+        account_id: AccountId,
+        stack_uuid: StackUuid,
         expected: i32,
         actual: i32,
     },
 
     #[error("Not enough balance for stack '{stack_uuid}': requested {qty}, had {balance}")]
     NotEnoughBalance {
-        account_id: String,
-        stack_uuid: u128,
-        qty: i32,
-        balance: i32,
+        account_id: AccountId,
+        stack_uuid: StackUuid,
+        qty: i64,
+        balance: i64,
+    },
+
+    #[error("Account '{account_id}' is frozen: {reason}")]
+    AccountFrozen {
+        account_id: AccountId,
+        reason: String,
+        expires_at: Option<i64>,
+    },
+
+    #[error("Account '{account_id}' was erased")]
+    AccountErased {
+        account_id: AccountId,
     },
 
+    #[error("No erasure key was set, see StackLedger::set_erasure_key")]
+    MissingErasureKey,
+
+    #[error("Another erasure key was already set")]
+    ErasureKeyAlreadySet,
+
+    #[error("Stack '{stack_uuid}' not found for account '{account_id}'")]
+    StackNotFound {
+        account_id: AccountId,
+        stack_uuid: StackUuid,
+    },
+
+    #[error("Account '{account_id}' has no inventory")]
+    AccountNotFound {
+        account_id: AccountId,
+    },
+
+    #[error("Stack '{stack_uuid}' was already consumed")]
+    AlreadyConsumed {
+        stack_uuid: StackUuid,
+    },
+
+    #[error("Sequence number {sequence_number} of stack '{stack_uuid}' already exists for account '{account_id}'")]
+    SequenceConflict {
+        account_id: AccountId,
+        stack_uuid: StackUuid,
+        sequence_number: i32,
+    },
+
+    #[error("Stack '{stack_uuid}' of account '{account_id}' changed: expected version {expected}, found {actual}")]
+    StaleVersion {
+        account_id: AccountId,
+        stack_uuid: StackUuid,
+        expected: i32,
+        actual: i32,
+    },
+
+    #[error("Stack '{stack_uuid}' needs the sequence number the player saw")]
+    MissingSequenceNumber {
+        stack_uuid: StackUuid,
+    },
+
+    #[error("Quantity {qty} does not fit in a 64-bit ledger quantity")]
+    QuantityOutOfRange {
+        qty: u64,
+    },
+
+    #[error("Balance of stack '{stack_uuid}' would overflow: had {balance}, adding {qty}")]
+    BalanceOverflow {
+        account_id: AccountId,
+        stack_uuid: StackUuid,
+        qty: i64,
+        balance: i64,
+    },
+
+    #[error("Total of item type {item_type} does not fit in 64 bits")]
+    TotalOverflow {
+        item_type: i32,
+    },
+
+    #[error("Batch breaks conservation of item type {item_type}: created {created}, destroyed {destroyed}")]
+    ConservationViolated {
+        item_type: i32,
+        created: i64,
+        destroyed: i64,
+    },
+
+    #[error("Recipe {recipe_id} turns item types into others, so a batch for it can't check {conservation:?}")]
+    ConservationOnCraft {
+        recipe_id: i32,
+        conservation: Conservation,
+    },
+
+    #[error("Partition '{partition}' has an unexpected bound: {bound}")]
+    InvalidPartitionBound {
+        partition: String,
+        bound: String,
+    },
+
+    #[error("Error writing export: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Error writing CSV export: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("Error writing Parquet export: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+
+    #[error("Invalid seed file: {0}")]
+    InvalidSeed(#[from] serde_json::Error),
+
+    #[error("Invalid item migration '{migration_id}': {reason}")]
+    InvalidMigration {
+        migration_id: String,
+        reason: &'static str,
+    },
+
+    #[error("Block type {block_type} can't be looted")]
+    NotLootable {
+        block_type: i32,
+    },
+
+    #[error("There is no block at ({x}, {y}, {z}, {a})")]
+    EmptyBlock {
+        x: i128,
+        y: i128,
+        z: i128,
+        a: u32,
+    },
+
+    #[error("Block at ({x}, {y}, {z}, {a}) is no longer a {block_type}")]
+    BlockChanged {
+        x: i128,
+        y: i128,
+        z: i128,
+        a: u32,
+        block_type: i32,
+    },
+
+    #[error("Error from the world: {0}")]
+    World(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Invalid loot table for block type {block_type}: {reason}")]
+    InvalidLootTable {
+        block_type: i32,
+        reason: &'static str,
+    },
+
+    #[error("Invalid loot tables file: {0}")]
+    InvalidLootTablesFile(serde_json::Error),
+
 }
 
-fn compute_xyza_uuid(x: i128, y: i128, z: i128, a: u32) -> u128 {
+fn compute_xyza_uuid(x: i128, y: i128, z: i128, a: u32) -> StackUuid {
     let mut bytes = [0u8; 52];  
     
     bytes[0..16].copy_from_slice(&x.to_le_bytes());
@@ -34,38 +238,64 @@ fn compute_xyza_uuid(x: i128, y: i128, z: i128, a: u32) -> u128 {
     bytes[32..48].copy_from_slice(&z.to_le_bytes());
     bytes[48..52].copy_from_slice(&a.to_le_bytes());
     
-    XxHash3_128::oneshot(&bytes)
+    StackUuid::new(XxHash3_128::oneshot(&bytes))
+}
+
+/// Checks the expected version of a stack that is credited or created. The account may not hold
+/// the stack yet, so a missing row always passes
+pub(crate) fn check_expected_version(expected_sequence_number: Option<i32>, actual: Option<i32>, account_id: &AccountId, stack_uuid: StackUuid) -> Result<(), Error> {
+    match (expected_sequence_number, actual) {
+        (Some(expected), Some(actual)) if expected != actual => Err(Error::StaleVersion {
+            account_id: account_id.clone(),
+            stack_uuid,
+            expected,
+            actual,
+        }),
+        _ => Ok(()),
+    }
 }
 
-fn compute_latest_key(account_id: &str, stack_uuid: u128) -> u128 {
-    let account_bytes = account_id.as_bytes();
-    let uuid_bytes = stack_uuid.to_le_bytes();
-    let mut bytes = Vec::with_capacity(account_bytes.len() + 16);
-    bytes.extend_from_slice(account_bytes);
-    bytes.extend_from_slice(&uuid_bytes);
-    XxHash3_128::oneshot(&bytes)
+/// Quantities are unsigned in the API but stored as BIGINT
+pub(crate) fn checked_qty(qty: u64) -> Result<i64, Error> {
+    i64::try_from(qty).map_err(|_| Error::QuantityOutOfRange { qty })
 }
 
-fn compute_composite_key_bytes(account_id: &str, stack_uuid: u128, sequence_number: i32) -> Vec<u8> {
-    let account_bytes = account_id.as_bytes();
-    let uuid_bytes = stack_uuid.to_le_bytes();
-    let seq_num_bytes = sequence_number.to_le_bytes();
-    let mut bytes = Vec::with_capacity(account_bytes.len() + 16 + 4);
-    bytes.extend_from_slice(account_bytes);
-    bytes.extend_from_slice(&uuid_bytes);
-    bytes.extend_from_slice(&seq_num_bytes);
-    let composite_key = XxHash3_128::oneshot(&bytes);
-
-    composite_key.to_le_bytes().to_vec()
+/// Derived from the versions the craft expects its inputs at, before they are debited. Replaying
+/// the same craft gets the same uuid, so it fails on the moved inputs or collides in `consumed`
+/// instead of minting twice
+fn compute_craft_uuid_key(inputs: &[(StackUuid, i32)], nonce: u64) -> StackUuid {
+    let mut inputs = inputs.to_vec();
+    inputs.sort_unstable();
+
+    let mut bytes = Vec::with_capacity(8 + inputs.len() * 20);
+    bytes.extend_from_slice(&nonce.to_le_bytes());
+    for (stack_uuid, sequence_number) in inputs {
+        bytes.extend_from_slice(&stack_uuid.to_le_bytes());
+        bytes.extend_from_slice(&sequence_number.to_le_bytes());
+    }
+
+    StackUuid::new(XxHash3_128::oneshot(&bytes))
 }
 
-fn compute_craft_uuid_key() -> u128 {
-    fastrand::u128(..)
+
+pub struct StackLedger<B = PgPool> {
+    backend: B,
+    trackers: Vec<WriteTracker>,
 }
 
+impl<B: LedgerBackend> StackLedger<B> {
+
+    pub fn with_backend(backend: B) -> Self {
+        Self {
+            backend,
+            trackers: Vec::new(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
 
-pub struct StackLedger {
-    pool: PgPool,
 }
 
 impl StackLedger {
@@ -73,65 +303,89 @@ impl StackLedger {
     pub async fn connect(connection: &str) -> Result<Self, sqlx::Error> {
         let pool = PgPool::connect(connection).await?;
         Ok(Self {
-            pool
+            backend: pool,
+            trackers: Vec::new(),
         })
     }
 
     pub async fn new(pool: PgPool) -> Self {
         Self {
-            pool
+            backend: pool,
+            trackers: Vec::new(),
         }
     }
 
-    async fn create(tx: &mut Transaction<'_, Postgres>, stack_uuid: u128, item_type: i32, qty: u32, account_id: &str) -> Result<(), Error> {
+    #[allow(clippy::too_many_arguments)]
+    async fn create(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, item_type: i32, expected_sequence_number: Option<i32>, qty: u64, account_id: &AccountId, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
 
-        let latest_key = compute_latest_key(account_id, stack_uuid);
-        let stack_uuid_bytes = stack_uuid.to_le_bytes();
+        let latest_key = compute_latest_key(CURRENT_KEY_VERSION, account_id, stack_uuid);
         let latest_key_bytes = latest_key.to_le_bytes();
-        let composite_key_bytes = compute_composite_key_bytes(account_id, stack_uuid, 0);
+        let composite_key_bytes = compute_composite_key_bytes(CURRENT_KEY_VERSION, account_id, stack_uuid, 0);
+        let qty = checked_qty(qty)?;
+        let entry_hash = ChainedEntry {
+            account_id,
+            stack_uuid,
+            sequence_number: 0,
+            qty,
+            balance: qty,
+            item_type,
+            operation: Operation::Create,
+            reason,
+        }.hash(&GENESIS_HASH);
+
+        if expected_sequence_number.is_some() {
+            let actual = Self::lock_stack(tx, account_id, stack_uuid).await?
+                .map(|stack| stack.sequence_number);
+            check_expected_version(expected_sequence_number, actual, account_id, stack_uuid)?;
+        }
+
+        Self::ensure_not_erased(tx, account_id).await?;
 
         // Garantiza que solo un jugador pueda obtener el drop
-        sqlx::query!(
-            r#"INSERT INTO consumed (stack_uuid)
-            VALUES ($1);
-            "#,
-            stack_uuid_bytes.as_slice())
-            .execute(&mut **tx)
-            .await?;
+        Self::consume(tx, stack_uuid, reason).await?;
 
-        let ledger_entry = sqlx::query!(
-            r#"INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, qty, balance, item_type)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            RETURNING key;
+        let ledger_entry = sqlx::query_as!(ReceiptEntry,
+            r#"INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, key_version, qty, balance, item_type, operation, reason, entry_hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            RETURNING key, account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number, qty, balance, item_type, operation AS "operation: _", entry_hash AS "entry_hash!";
             "#,
-            account_id,
-            stack_uuid_bytes.as_slice(),
+            account_id as &AccountId,
+            stack_uuid as StackUuid,
             0,
             composite_key_bytes.as_slice(),
-            qty as i32,
-            qty as i32,
-            item_type)
+            CURRENT_KEY_VERSION as i16,
+            qty,
+            qty,
+            item_type,
+            Operation::Create as Operation,
+            reason.map(Json) as _,
+            entry_hash.as_slice())
             .fetch_one(&mut **tx)
             .await?;
 
         // Sirve para posteriores inserciones al ledger
+        let leaf_hash = compute_leaf_hash(&latest_key_bytes, stack_uuid, 0, qty, item_type, &entry_hash);
         sqlx::query!(
-            r#"INSERT INTO latest (key, account_id, stack_uuid, sequence_number, balance, item_type)
-            VALUES ($1, $2, $3, $4, $5, $6);
+            r#"INSERT INTO latest (key, key_version, account_id, stack_uuid, sequence_number, balance, item_type, entry_hash, leaf_hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
             "#,
             latest_key_bytes.as_slice(),
-            account_id,
-            stack_uuid_bytes.as_slice(),
+            CURRENT_KEY_VERSION as i16,
+            account_id as &AccountId,
+            stack_uuid as StackUuid,
             0,
-            qty as i32,
-            item_type)
+            qty,
+            item_type,
+            entry_hash.as_slice(),
+            leaf_hash.as_slice())
             .execute(&mut **tx)
             .await?;
+        rebuild_tree(tx, account_id).await?;
 
        sqlx::query!(
            r#"INSERT INTO stacks (stack_uuid, latest_keys, ledger_entries)
            VALUES ($1, $2, $3);"#,
-           stack_uuid_bytes.as_slice(),
+           stack_uuid as StackUuid,
            &[latest_key_bytes.to_vec()],
            &[ledger_entry.key])
            .execute(&mut **tx)
@@ -142,34 +396,95 @@ impl StackLedger {
             SET latest_keys = array_append(latest_keys, $1)
             WHERE account_id = $2;"#,
             latest_key_bytes.as_slice(), 
-            account_id)
+            account_id as &AccountId)
             .execute(&mut **tx)
             .await?;
 
-        Ok(())
+        Ok(ledger_entry)
     }
 
-    pub async fn destroy(tx: &mut Transaction<'_, Postgres>, stack_uuid: u128, expected_item_type: i32, account_id: &str, qty: u32) -> Result<(), Error> {
+    pub async fn destroy(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        Self::ensure_not_frozen(tx, account_id).await?;
+        Self::debit(tx, stack_uuid, expected_item_type, expected_sequence_number, account_id, qty, Operation::Destroy, reason).await
+    }
 
-        let stack_uuid_bytes = stack_uuid.to_le_bytes();
-        let latest_key = compute_latest_key(account_id, stack_uuid);
-        let latest_key_bytes = latest_key.to_le_bytes();
-        let qty = qty as i32;
+    /// Same as `destroy`, but goes through even if the account is frozen
+    pub async fn admin_destroy(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        Self::debit(tx, stack_uuid, expected_item_type, expected_sequence_number, account_id, qty, Operation::Destroy, reason).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn split(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<(ReceiptEntry, ReceiptEntry), Error> {
+        Self::ensure_not_frozen(tx, sender_id).await?;
+        let debit = Self::debit(tx, stack_uuid, expected_item_type, expected_sequence_number, sender_id, qty, Operation::SplitOut, reason).await?;
+        let credit = Self::credit(tx, stack_uuid, expected_item_type, expected_recipient_sequence_number, recipient_id, qty, reason).await?;
+        Ok((debit, credit))
+    }
+
+    /// Same as `split`, but goes through even if the sender is frozen
+    #[allow(clippy::too_many_arguments)]
+    pub async fn admin_split(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<(ReceiptEntry, ReceiptEntry), Error> {
+        let debit = Self::debit(tx, stack_uuid, expected_item_type, expected_sequence_number, sender_id, qty, Operation::SplitOut, reason).await?;
+        let credit = Self::credit(tx, stack_uuid, expected_item_type, expected_recipient_sequence_number, recipient_id, qty, reason).await?;
+        Ok((debit, credit))
+    }
+
+    /// Current version of the stack, locked until the transaction ends
+    pub async fn lock_stack(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, stack_uuid: StackUuid) -> Result<Option<Stack>, Error> {
+
+        let stack = sqlx::query_as!(Stack,
+            r#"SELECT stack_uuid AS "stack_uuid: StackUuid", sequence_number, balance, item_type
+            FROM latest
+            WHERE account_id = $1 AND stack_uuid = $2
+            FOR UPDATE;"#,
+            account_id as &AccountId,
+            stack_uuid as StackUuid)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        Ok(stack)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn debit(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, operation: Operation, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+
+        // Rows that weren't rekeyed yet still have the legacy key
+        let latest_key_bytes = compute_latest_key(CURRENT_KEY_VERSION, account_id, stack_uuid).to_le_bytes();
+        let legacy_key_bytes = compute_latest_key(KeyVersion::V0, account_id, stack_uuid).to_le_bytes();
+        let qty = checked_qty(qty)?;
 
         let latest = sqlx::query!(r#"
-        SELECT sequence_number, balance, item_type
+        SELECT key, sequence_number, balance, item_type, entry_hash
         FROM latest
-        WHERE key = $1;
+        WHERE (key = $1 OR key = $2) AND account_id = $3
+        FOR UPDATE;
         "#,
-        latest_key_bytes.as_slice())
-            .fetch_one(&mut **tx)
-            .await?;
+        latest_key_bytes.as_slice(),
+        legacy_key_bytes.as_slice(),
+        account_id as &AccountId)
+            .fetch_optional(&mut **tx)
+            .await?
+            .ok_or_else(|| Error::StackNotFound {
+                account_id: account_id.clone(),
+                stack_uuid,
+            })?;
+
+        // The row is locked, so nobody can move the stack past this version before we commit
+        if let Some(expected) = expected_sequence_number
+            && expected != latest.sequence_number {
+            return Err(Error::StaleVersion {
+                account_id: account_id.clone(),
+                stack_uuid,
+                expected,
+                actual: latest.sequence_number,
+            });
+        }
         
         // Check if client is lying about the item type
         if expected_item_type != latest.item_type {
             // tx.rollback().await?; Rollback happens automatically
             return Err(Error::ItemTypeMismatch {
-                account_id: account_id.to_string(),
+                account_id: account_id.clone(),
                 stack_uuid,
                 expected: expected_item_type,
                 actual: latest.item_type,
@@ -179,39 +494,59 @@ impl StackLedger {
         if qty > latest.balance {
             // tx.rollback().await?; 
             return Err(Error::NotEnoughBalance {
-                account_id: account_id.to_string(),
+                account_id: account_id.clone(),
                 stack_uuid,
                 qty,
                 balance: latest.balance
             });
         }
 
-        let composite_key_bytes = compute_composite_key_bytes(account_id, stack_uuid, latest.sequence_number + 1);
-        sqlx::query!(r#"
-        INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, qty, balance, item_type)
-        VALUES ($1, $2, $3, $4, $5, $6, $7);
+        let composite_key_bytes = compute_composite_key_bytes(CURRENT_KEY_VERSION, account_id, stack_uuid, latest.sequence_number + 1);
+        let entry_hash = ChainedEntry {
+            account_id,
+            stack_uuid,
+            sequence_number: latest.sequence_number + 1,
+            qty: -qty,
+            balance: latest.balance - qty,
+            item_type: latest.item_type,
+            operation,
+            reason,
+        }.hash(latest.entry_hash.as_deref().unwrap_or(&GENESIS_HASH));
+
+        let ledger_entry = sqlx::query_as!(ReceiptEntry, r#"
+        INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, key_version, qty, balance, item_type, operation, reason, entry_hash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING key, account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number, qty, balance, item_type, operation AS "operation: _", entry_hash AS "entry_hash!";
         "#,
-        account_id,
-        stack_uuid_bytes.as_slice(),
+        account_id as &AccountId,
+        stack_uuid as StackUuid,
         latest.sequence_number + 1,
         composite_key_bytes.as_slice(),
-        -(qty as i32),
-        latest.balance -(qty as i32),
-        latest.item_type)
-            .execute(&mut **tx)
+        CURRENT_KEY_VERSION as i16,
+        -qty,
+        latest.balance - qty,
+        latest.item_type,
+        operation as Operation,
+        reason.map(Json) as _,
+        entry_hash.as_slice())
+            .fetch_one(&mut **tx)
             .await?;
 
+        let leaf_hash = compute_leaf_hash(&latest.key, stack_uuid, latest.sequence_number + 1, latest.balance - qty, latest.item_type, &entry_hash);
         sqlx::query!(r#"
         UPDATE latest 
-        SET sequence_number = $1, balance = $2
+        SET sequence_number = $1, balance = $2, entry_hash = $5, leaf_hash = $6
         WHERE account_id = $3 AND stack_uuid = $4;
         "#,
         latest.sequence_number + 1,
-        latest.balance + qty,
-        account_id,
-        stack_uuid_bytes.as_slice())
+        latest.balance - qty,
+        account_id as &AccountId,
+        stack_uuid as StackUuid,
+        entry_hash.as_slice(),
+        leaf_hash.as_slice())
             .execute(&mut **tx)
             .await?;
+        set_leaf(tx, account_id, &latest.key, leaf_hash).await?;
 
         let empty_stack: bool = latest.balance == qty;           
 
@@ -220,8 +555,8 @@ impl StackLedger {
             UPDATE inventories
             SET latest_keys = array_remove(latest_keys, $1)
             WHERE account_id = $2;"#,
-            latest_key_bytes.as_slice(),
-            account_id)
+            latest.key,
+            account_id as &AccountId)
                 .execute(&mut **tx)
                 .await?;
 
@@ -229,62 +564,91 @@ impl StackLedger {
             UPDATE stacks
             SET latest_keys = array_remove(latest_keys, $1)
             WHERE stack_uuid = $2;"#,
-            latest_key_bytes.as_slice(),
-            stack_uuid_bytes.as_slice())
+            latest.key,
+            stack_uuid as StackUuid)
                 .execute(&mut **tx)
                 .await?;
 
         }
 
-            Ok(())
+        Ok(ledger_entry)
     }
 
-    pub async fn split(tx: &mut Transaction<'_, Postgres>, stack_uuid: u128, expected_item_type: i32, sender_id: &str, recipient_id: &str, qty: u32) -> Result<(), Error> {
-
-        Self::destroy(tx, stack_uuid, expected_item_type, sender_id, qty).await?;
-        
-        let stack_uuid_bytes = stack_uuid.to_le_bytes();
-        let qty = qty as i32;
+    #[allow(clippy::too_many_arguments)]
+    async fn credit(tx: &mut Transaction<'_, Postgres>, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        let qty = checked_qty(qty)?;
+        Self::ensure_not_erased(tx, recipient_id).await?;
 
         let result = sqlx::query!(
-            r#"SELECT  key, sequence_number, balance, item_type
+            r#"SELECT  key, sequence_number, balance, item_type, entry_hash
             FROM latest
-            WHERE account_id = $1 AND stack_uuid = $2;
+            WHERE account_id = $1 AND stack_uuid = $2
+            FOR UPDATE;
             "#,
-            recipient_id,
-            stack_uuid_bytes.as_slice())
+            recipient_id as &AccountId,
+            stack_uuid as StackUuid)
             .fetch_one(&mut **tx)
             .await;
 
-        match result {
+        let ledger_entry = match result {
             Ok(latest) => {
 
-                let composite_key_bytes = compute_composite_key_bytes(recipient_id, stack_uuid, latest.sequence_number + 1);
-
-                sqlx::query!(
-                    r#"INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, qty, balance, item_type)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7);"#,
-                    recipient_id,
-                    stack_uuid_bytes.as_slice(),
+                check_expected_version(expected_sequence_number, Some(latest.sequence_number), recipient_id, stack_uuid)?;
+
+                let balance = latest.balance
+                    .checked_add(qty)
+                    .ok_or_else(|| Error::BalanceOverflow {
+                        account_id: recipient_id.clone(),
+                        stack_uuid,
+                        qty,
+                        balance: latest.balance,
+                    })?;
+
+                let composite_key_bytes = compute_composite_key_bytes(CURRENT_KEY_VERSION, recipient_id, stack_uuid, latest.sequence_number + 1);
+                let entry_hash = ChainedEntry {
+                    account_id: recipient_id,
+                    stack_uuid,
+                    sequence_number: latest.sequence_number + 1,
+                    qty,
+                    balance,
+                    item_type: latest.item_type,
+                    operation: Operation::SplitIn,
+                    reason,
+                }.hash(latest.entry_hash.as_deref().unwrap_or(&GENESIS_HASH));
+
+                let ledger_entry = sqlx::query_as!(ReceiptEntry,
+                    r#"INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, key_version, qty, balance, item_type, operation, reason, entry_hash)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                    RETURNING key, account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number, qty, balance, item_type, operation AS "operation: _", entry_hash AS "entry_hash!";"#,
+                    recipient_id as &AccountId,
+                    stack_uuid as StackUuid,
                     latest.sequence_number + 1,
                     composite_key_bytes.as_slice(),
+                    CURRENT_KEY_VERSION as i16,
                     qty,
-                    latest.balance + qty,
-                    latest.item_type)
-                    .execute(&mut **tx)
+                    balance,
+                    latest.item_type,
+                    Operation::SplitIn as Operation,
+                    reason.map(Json) as _,
+                    entry_hash.as_slice())
+                    .fetch_one(&mut **tx)
                     .await?;
 
+                let leaf_hash = compute_leaf_hash(&latest.key, stack_uuid, latest.sequence_number + 1, balance, latest.item_type, &entry_hash);
                 sqlx::query!(
                     r#"UPDATE latest 
-                    SET sequence_number = $1, balance = $2
+                    SET sequence_number = $1, balance = $2, entry_hash = $5, leaf_hash = $6
                     WHERE account_id = $3 AND stack_uuid = $4;
                     "#,
                     latest.sequence_number + 1,
-                    latest.balance + qty,
-                    recipient_id,
-                    stack_uuid_bytes.as_slice())
+                    balance,
+                    recipient_id as &AccountId,
+                    stack_uuid as StackUuid,
+                    entry_hash.as_slice(),
+                    leaf_hash.as_slice())
                     .execute(&mut **tx)
                     .await?;
+                set_leaf(tx, recipient_id, &latest.key, leaf_hash).await?;
 
                 // Having a balance of 0 means the user has had this stack, but the balance was
                 // empty, so stacks and inventories were updated previously and now we need to
@@ -296,7 +660,7 @@ impl StackLedger {
                         SET latest_keys = array_append(latest_keys, $1)
                         WHERE account_id = $2;"#,
                         latest.key,
-                        recipient_id)
+                        recipient_id as &AccountId)
                         .execute(&mut **tx)
                         .await?;
 
@@ -305,54 +669,75 @@ impl StackLedger {
                         SET latest_keys = array_append(latest_keys, $1)
                         WHERE stack_uuid = $2;"#,
                         latest.key,
-                        stack_uuid_bytes.as_slice())
+                        stack_uuid as StackUuid)
                         .execute(&mut **tx)
                         .await?;
 
                 }
 
+                ledger_entry
             },
 
             Err(sqlx::Error::RowNotFound) => {
 
-                let composite_key_bytes = compute_composite_key_bytes(recipient_id, stack_uuid, 0);
-
-                sqlx::query!(
-                    r#"INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, qty, balance, item_type)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7);
+                let composite_key_bytes = compute_composite_key_bytes(CURRENT_KEY_VERSION, recipient_id, stack_uuid, 0);
+                let entry_hash = ChainedEntry {
+                    account_id: recipient_id,
+                    stack_uuid,
+                    sequence_number: 0,
+                    qty,
+                    balance: qty,
+                    item_type: expected_item_type,
+                    operation: Operation::SplitIn,
+                    reason,
+                }.hash(&GENESIS_HASH);
+
+                let ledger_entry = sqlx::query_as!(ReceiptEntry,
+                    r#"INSERT INTO ledger (account_id, stack_uuid, sequence_number, composite, key_version, qty, balance, item_type, operation, reason, entry_hash)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                    RETURNING key, account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number, qty, balance, item_type, operation AS "operation: _", entry_hash AS "entry_hash!";
                     "#,
-                    recipient_id,
-                    stack_uuid_bytes.as_slice(),
+                    recipient_id as &AccountId,
+                    stack_uuid as StackUuid,
                     0,
                     composite_key_bytes.as_slice(),
+                    CURRENT_KEY_VERSION as i16,
                     qty,
                     qty,
-                    expected_item_type)
-                    .execute(&mut **tx)
+                    expected_item_type,
+                    Operation::SplitIn as Operation,
+                    reason.map(Json) as _,
+                    entry_hash.as_slice())
+                    .fetch_one(&mut **tx)
                     .await?;
 
-                let latest_key = compute_latest_key(recipient_id, stack_uuid);
+                let latest_key = compute_latest_key(CURRENT_KEY_VERSION, recipient_id, stack_uuid);
                 let latest_key_bytes = latest_key.to_le_bytes();
 
+                let leaf_hash = compute_leaf_hash(&latest_key_bytes, stack_uuid, 0, qty, expected_item_type, &entry_hash);
                 sqlx::query!(
-                    r#"INSERT INTO latest (key, account_id, stack_uuid, sequence_number, balance, item_type)
-                    VALUES ($1, $2, $3, $4, $5, $6);
+                    r#"INSERT INTO latest (key, key_version, account_id, stack_uuid, sequence_number, balance, item_type, entry_hash, leaf_hash)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
                     "#,
                     latest_key_bytes.as_slice(),
-                    recipient_id,
-                    stack_uuid_bytes.as_slice(),
+                    CURRENT_KEY_VERSION as i16,
+                    recipient_id as &AccountId,
+                    stack_uuid as StackUuid,
                     0,
                     qty,
-                    expected_item_type)
+                    expected_item_type,
+                    entry_hash.as_slice(),
+                    leaf_hash.as_slice())
                     .execute(&mut **tx)
                     .await?;
+                rebuild_tree(tx, recipient_id).await?;
 
                 sqlx::query!(
                     r#"UPDATE inventories
                     SET latest_keys = array_append(latest_keys, $1)
                     WHERE account_id = $2;"#,
                     latest_key_bytes.as_slice(),
-                    recipient_id)
+                    recipient_id as &AccountId)
                     .execute(&mut **tx)
                     .await?;
 
@@ -361,11 +746,11 @@ impl StackLedger {
                     SET latest_keys = array_append(latest_keys, $1)
                     WHERE stack_uuid = $2;"#,
                     latest_key_bytes.as_slice(),
-                    stack_uuid_bytes.as_slice())
+                    stack_uuid as StackUuid)
                     .execute(&mut **tx)
                     .await?;
 
-
+                ledger_entry
             },
 
             Err(e) => {
@@ -375,7 +760,7 @@ impl StackLedger {
 
         };
 
-        Ok(())
+        Ok(ledger_entry)
     }
 
 }
@@ -404,55 +789,115 @@ impl DimensionInventory {
     }
 }
 
-struct StackSlice {
-    stack_uuid: u128,
-    qty: u32,
+pub struct StackSlice {
+    stack_uuid: StackUuid,
+    qty: u64,
     expected_item_type: i32,
+    expected_sequence_number: Option<i32>,
+}
+
+impl StackSlice {
+    pub fn new(stack_uuid: StackUuid, qty: u64, expected_item_type: i32) -> Self {
+        Self {
+            stack_uuid,
+            qty,
+            expected_item_type,
+            expected_sequence_number: None,
+        }
+    }
+
+    /// Fails with `Error::StaleVersion` unless the stack is still at the version the player saw,
+    /// see `Stack::get_sequence_number`
+    pub fn with_sequence_number(mut self, sequence_number: i32) -> Self {
+        self.expected_sequence_number = Some(sequence_number);
+        self
+    }
 }
 
-trait InventoryActions {
+pub trait InventoryActions {
 
-    async fn create_from_xyza(tx: &mut Transaction<'_, Postgres>, x: i128, y: i128, z: i128, a: u32, item_type: i32, qty: u32, account_id: &str) -> Result<u128, Error>;
+    /// Mints what `loot_tables` rolls for the block `world` has at the position, never a type or
+    /// quantity chosen by the client, and breaks the block. The block is broken before the caller
+    /// commits, so a failed commit leaves it broken without a drop, never dropped twice
+    #[allow(clippy::too_many_arguments)]
+    fn create_from_xyza<T: LedgerTx, W: WorldBlocks>(tx: &mut T, world: &W, loot_tables: &LootTables, x: i128, y: i128, z: i128, a: u32, account_id: &AccountId) -> impl Future<Output = Result<StackUuid, Error>> + Send;
 
-    async fn drop(&self, account_id: &str, stack_slices: &[StackSlice], to_world: &str, expected_item_type: i32) -> Result<(), Error>;
+    fn drop(&self, account_id: &AccountId, stack_slices: &[StackSlice], to_world: &AccountId, expected_item_type: i32) -> impl Future<Output = Result<(), Error>> + Send;
 
-    async fn craft(&self, account_id: &str, stack_slices: &[StackSlice], qty: u32, crafted_item_type: i32) -> Result<u128, Error>;
+    /// Every slice needs `StackSlice::with_sequence_number`, the crafted uuid is derived from them
+    fn craft(&self, account_id: &AccountId, stack_slices: &[StackSlice], recipe_id: i32, nonce: u64, qty: u64, crafted_item_type: i32) -> impl Future<Output = Result<StackUuid, Error>> + Send;
  
 }
 
-impl InventoryActions for StackLedger {
-
-    async fn create_from_xyza(tx: &mut Transaction<'_, Postgres>, x: i128, y: i128, z: i128, a: u32, item_type: i32, qty: u32, account_id: &str) -> Result<u128, Error> {
-        let stack_uuid: u128 = compute_xyza_uuid(x, y, z, a); 
+impl<B: LedgerBackend> InventoryActions for StackLedger<B> {
 
-        Self::create(tx, stack_uuid, item_type, qty, account_id).await?;
-        Ok(stack_uuid)
-    }
+    async fn create_from_xyza<T: LedgerTx, W: WorldBlocks>(tx: &mut T, world: &W, loot_tables: &LootTables, x: i128, y: i128, z: i128, a: u32, account_id: &AccountId) -> Result<StackUuid, Error> {
+        let stack_uuid: StackUuid = compute_xyza_uuid(x, y, z, a); 
+        let reason = Reason::Loot { x, y, z, a };
 
-    async fn drop(&self, account_id: &str, stack_slices: &[StackSlice], to_world: &str, expected_item_type: i32) -> Result<(), Error> {
+        let block_type = world
+            .get_block(x, y, z, a).await?
+            .ok_or(Error::EmptyBlock { x, y, z, a })?;
+        let drop = loot_tables.roll(block_type, x, y, z, a)?;
 
-        let mut tx = self.pool.begin().await?;
+        // A looted position fails here, before its block is touched
+        tx.create(stack_uuid, drop.get_type(), None, drop.get_qty(), account_id, Some(&reason)).await?;
 
-        for stack_slice in stack_slices {
-            Self::split(&mut tx, stack_slice.stack_uuid, expected_item_type, account_id, to_world, stack_slice.qty).await?;
+        if !world.break_block(x, y, z, a, block_type).await? {
+            return Err(Error::BlockChanged { x, y, z, a, block_type });
         }
 
-        tx.commit().await?;
-        Ok(())
+        Ok(stack_uuid)
     }
 
-    async fn craft(&self, account_id: &str, stack_slices: &[StackSlice], qty: u32, crafted_item_type: i32) -> Result<u128, Error> {
-
-        let mut tx = self.pool.begin().await?;
+    async fn drop(&self, account_id: &AccountId, stack_slices: &[StackSlice], to_world: &AccountId, expected_item_type: i32) -> Result<(), Error> {
 
-        for stack_slice in stack_slices {
-            Self::destroy(&mut tx, stack_slice.stack_uuid, stack_slice.expected_item_type, account_id, stack_slice.qty).await?;
-        }
+        let batch = stack_slices
+            .iter()
+            .fold(LedgerBatch::new(), |batch, stack_slice| {
+                batch.split(stack_slice.stack_uuid, expected_item_type, stack_slice.expected_sequence_number, None, account_id, to_world, stack_slice.qty)
+            });
 
-        let crafted_stack_uuid = compute_craft_uuid_key();
+        self.execute_batch(&batch).await?;
+        Ok(())
+    }
 
-        Self::create(&mut tx, crafted_stack_uuid, crafted_item_type, qty, account_id).await?;
-        tx.commit().await?;
+    async fn craft(&self, account_id: &AccountId, stack_slices: &[StackSlice], recipe_id: i32, nonce: u64, qty: u64, crafted_item_type: i32) -> Result<StackUuid, Error> {
+
+        let expected_versions = stack_slices
+            .iter()
+            .map(|stack_slice| {
+                stack_slice.expected_sequence_number
+                    .map(|sequence_number| (stack_slice.stack_uuid, sequence_number))
+                    .ok_or(Error::MissingSequenceNumber { stack_uuid: stack_slice.stack_uuid })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let crafted_stack_uuid = compute_craft_uuid_key(&expected_versions, nonce);
+
+        let mut tx = self.backend.begin().await?;
+        let reason = Reason::Recipe { recipe_id, nonce };
+
+        let inputs = stack_slices
+            .iter()
+            .fold(LedgerBatch::new().reason(reason.clone()), |batch, stack_slice| {
+                batch.destroy(stack_slice.stack_uuid, stack_slice.expected_item_type, stack_slice.expected_sequence_number, account_id, stack_slice.qty)
+            });
+        let receipt = inputs.apply(&mut tx).await?;
+
+        let craft_inputs: Vec<CraftInput> = receipt
+            .get_entries()
+            .iter()
+            .map(|entry| CraftInput {
+                stack_uuid: entry.get_uuid(),
+                account_id: entry.get_account_id().clone(),
+                sequence_number: entry.get_sequence_number(),
+                qty: -entry.get_qty(),
+            })
+            .collect();
+
+        tx.create(crafted_stack_uuid, crafted_item_type, None, qty, account_id, Some(&reason)).await?;
+        tx.record_craft(crafted_stack_uuid, &craft_inputs).await?;
+        self.commit(tx, &[account_id]).await?;
 
         Ok(crafted_stack_uuid)
 
@@ -460,23 +905,26 @@ impl InventoryActions for StackLedger {
 
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Stack {
-    stack_uuid: Vec<u8>,
-    balance: i32,
+    stack_uuid: StackUuid,
+    sequence_number: i32,
+    balance: i64,
     item_type: i32,
 }
 
 impl Stack {
 
-    pub fn get_uuid(&self) -> Result<u128, std::array::TryFromSliceError> {
-        let uuid_bytes: [u8; 16] = self.stack_uuid
-            .as_slice()
-            .try_into()?;
-        Ok(u128::from_le_bytes(uuid_bytes))
+    pub fn get_uuid(&self) -> StackUuid {
+        self.stack_uuid
     }
 
-    pub fn get_balance(&self) -> i32 {
+    /// Version of the stack for this account, bumped by every entry written to it
+    pub fn get_sequence_number(&self) -> i32 {
+        self.sequence_number
+    }
+
+    pub fn get_balance(&self) -> i64 {
         self.balance
     }
 
@@ -486,6 +934,7 @@ impl Stack {
 
 }
 
+#[derive(Clone, Debug)]
 pub struct Inventory {
     stacks: Vec<Stack>
 }
@@ -496,32 +945,104 @@ impl Inventory {
             stacks: stacks.to_vec(),
         }
     }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Stack> {
+        self.stacks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stacks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    pub fn get_stack(&self, stack_uuid: StackUuid) -> Option<&Stack> {
+        self.stacks.iter().find(|stack| stack.stack_uuid == stack_uuid)
+    }
+
+    pub fn stacks_of_type(&self, item_type: i32) -> impl Iterator<Item = &Stack> {
+        self.stacks.iter().filter(move |stack| stack.item_type == item_type)
+    }
+
+    /// Summed balance of every stack of `item_type`
+    pub fn get_total(&self, item_type: i32) -> Result<i64, Error> {
+        self.stacks_of_type(item_type)
+            .try_fold(0i64, |total, stack| total.checked_add(stack.balance))
+            .ok_or(Error::TotalOverflow { item_type })
+    }
+
+    pub fn get_totals(&self) -> Result<HashMap<i32, i64>, Error> {
+        let mut totals = HashMap::new();
+        for stack in &self.stacks {
+            let total: &mut i64 = totals.entry(stack.item_type).or_insert(0);
+            *total = total
+                .checked_add(stack.balance)
+                .ok_or(Error::TotalOverflow { item_type: stack.item_type })?;
+        }
+        Ok(totals)
+    }
+}
+
+impl<'a> IntoIterator for &'a Inventory {
+    type Item = &'a Stack;
+    type IntoIter = std::slice::Iter<'a, Stack>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.stacks.iter()
+    }
 }
 
 pub struct InventoryManager {
-    pool: PgPool
+    pool: PgPool,
+    cache: Option<Arc<InventoryCache>>,
+    replica: Option<Arc<ReadReplica>>,
 }
 
 impl InventoryManager {
 
     pub async fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self { pool, cache: None, replica: None }
+    }
+
+    /// Fails with `Error::AccountNotFound` if the account has no inventories row
+    pub async fn get_inventory(&self, account_id: &AccountId) -> Result<Inventory, Error> {
+
+        let Some(cache) = self.cache.as_ref().filter(|cache| cache.is_listening()) else {
+            return self.fetch_inventory(self.read_pool(Some(account_id)).await, account_id).await;
+        };
+
+        match cache.get(account_id) {
+            Ok(inventory) => Ok(inventory),
+            Err(token) => {
+                // Invalidations come from the primary, a fill from a lagging replica would stay
+                // cached until the next change
+                let inventory = self.fetch_inventory(&self.pool, account_id).await?;
+                cache.fill(account_id, token, &inventory);
+                Ok(inventory)
+            },
+        }
     }
 
-    pub async fn get_inventory(&self, account_id: &str) -> Result<Inventory, sqlx::Error> {
+    async fn fetch_inventory(&self, pool: &PgPool, account_id: &AccountId) -> Result<Inventory, Error> {
 
         let inventory_row = sqlx::query!(
             r#"SELECT latest_keys FROM inventories
             WHERE account_id = $1;"#,
-            account_id)
-            .fetch_one(&self.pool)
-            .await?;
+            account_id as &AccountId)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| Error::AccountNotFound {
+                account_id: account_id.clone(),
+            })?;
         
         let stacks = sqlx::query_as!(Stack,
-            r#"SELECT stack_uuid, balance, item_type FROM latest
-            WHERE key = ANY($1);"#,
-            &inventory_row.latest_keys)
-            .fetch_all(&self.pool)
+            r#"SELECT stack_uuid AS "stack_uuid: StackUuid", sequence_number, balance, item_type FROM latest
+            WHERE key = ANY($1) AND account_id = $2;"#,
+            &inventory_row.latest_keys,
+            account_id as &AccountId)
+            .fetch_all(pool)
             .await?;
 
 
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use sqlx::{Transaction, Postgres};
+use sqlx::types::Json;
+use crate::{AccountId, Error, InventoryManager, Reason, StackLedger, StackUuid};
+
+/// Part of a stack consumed by a craft
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CraftInput {
+    pub(crate) stack_uuid: StackUuid,
+    pub(crate) account_id: AccountId,
+    pub(crate) sequence_number: i32,
+    pub(crate) qty: i64,
+}
+
+impl CraftInput {
+
+    pub fn get_uuid(&self) -> StackUuid {
+        self.stack_uuid
+    }
+
+    pub fn get_account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    /// Sequence number of the destroy entry written for the input
+    pub fn get_sequence_number(&self) -> i32 {
+        self.sequence_number
+    }
+
+    pub fn get_qty(&self) -> i64 {
+        self.qty
+    }
+
+}
+
+/// A stack and the stacks crafted into it, down to stacks that weren't crafted (loot, seeds,
+/// migrations, or created without a reason)
+#[derive(Clone, Debug)]
+pub struct LineageNode {
+    stack_uuid: StackUuid,
+    item_type: i32,
+    reason: Option<Reason>,
+    inputs: Vec<(CraftInput, LineageNode)>,
+}
+
+impl LineageNode {
+
+    pub fn get_uuid(&self) -> StackUuid {
+        self.stack_uuid
+    }
+
+    pub fn get_type(&self) -> i32 {
+        self.item_type
+    }
+
+    /// Reason of the entry that created the stack
+    pub fn get_reason(&self) -> Option<&Reason> {
+        self.reason.as_ref()
+    }
+
+    /// Empty unless the stack was crafted
+    pub fn get_inputs(&self) -> &[(CraftInput, LineageNode)] {
+        &self.inputs
+    }
+
+    /// Every looted stack the lineage goes back to
+    pub fn get_loot(&self) -> Vec<&LineageNode> {
+        let mut loot = Vec::new();
+        let mut pending = vec![self];
+
+        while let Some(node) = pending.pop() {
+            if let Some(Reason::Loot { .. }) = node.reason {
+                loot.push(node);
+            }
+            pending.extend(node.inputs.iter().map(|(_, input)| input));
+        }
+
+        loot
+    }
+
+}
+
+impl StackLedger {
+
+    pub(crate) async fn record_craft(tx: &mut Transaction<'_, Postgres>, output_uuid: StackUuid, inputs: &[CraftInput]) -> Result<(), Error> {
+
+        for input in inputs {
+            sqlx::query!(
+                r#"INSERT INTO craft_inputs (output_uuid, input_uuid, account_id, sequence_number, qty)
+                VALUES ($1, $2, $3, $4, $5);"#,
+                output_uuid as StackUuid,
+                input.stack_uuid as StackUuid,
+                &input.account_id as &AccountId,
+                input.sequence_number,
+                input.qty)
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+}
+
+struct Created {
+    item_type: i32,
+    reason: Option<Reason>,
+    inputs: Vec<CraftInput>,
+}
+
+impl InventoryManager {
+
+    /// Inputs consumed by the craft that created the stack, empty if it wasn't crafted
+    pub async fn get_craft_inputs(&self, output_uuid: StackUuid) -> Result<Vec<CraftInput>, sqlx::Error> {
+
+        sqlx::query_as!(CraftInput,
+            r#"SELECT input_uuid AS "stack_uuid: StackUuid", account_id AS "account_id: AccountId", sequence_number, qty
+            FROM craft_inputs
+            WHERE output_uuid = $1;"#,
+            output_uuid as StackUuid)
+            .fetch_all(self.read_pool(None).await)
+            .await
+    }
+
+    /// Follows the stack back through up to `max_depth` crafts. None if the stack was never
+    /// created. Crafted stacks `max_depth` crafts away are listed without their inputs, and a
+    /// `Reason::Migration` node can be followed further from the stack it names
+    pub async fn get_craft_lineage(&self, stack_uuid: StackUuid, max_depth: u32) -> Result<Option<LineageNode>, sqlx::Error> {
+
+        let max_depth = i32::try_from(max_depth).unwrap_or(i32::MAX);
+
+        // One row per craft input, or per stack for stacks without any. Inputs can be shared
+        // between crafts, each stack is only read at its shallowest depth
+        let rows = sqlx::query!(
+            r#"WITH RECURSIVE lineage (stack_uuid, depth) AS (
+                SELECT $1::BYTEA, 0
+                UNION
+                SELECT craft_inputs.input_uuid, lineage.depth + 1
+                FROM lineage
+                JOIN craft_inputs ON craft_inputs.output_uuid = lineage.stack_uuid
+                WHERE lineage.depth < $2
+            ), stacks AS (
+                SELECT stack_uuid, min(depth) AS depth
+                FROM lineage
+                GROUP BY stack_uuid
+            )
+            SELECT
+                stacks.stack_uuid AS "stack_uuid!: StackUuid",
+                created.item_type AS "item_type!",
+                created.reason AS "reason: Json<Reason>",
+                craft_inputs.input_uuid AS "input_uuid?: StackUuid",
+                craft_inputs.account_id AS "input_account_id?: AccountId",
+                craft_inputs.sequence_number AS "input_sequence_number?",
+                craft_inputs.qty AS "input_qty?"
+            FROM stacks
+            JOIN ledger_history created ON created.stack_uuid = stacks.stack_uuid AND created.operation = 'create'
+            LEFT JOIN craft_inputs ON craft_inputs.output_uuid = stacks.stack_uuid AND stacks.depth < $2;"#,
+            stack_uuid as StackUuid,
+            max_depth)
+            .fetch_all(self.read_pool(None).await)
+            .await?;
+
+        let mut created: HashMap<StackUuid, Created> = HashMap::new();
+        for row in rows {
+            let stack = created.entry(row.stack_uuid).or_insert_with(|| Created {
+                item_type: row.item_type,
+                reason: row.reason.map(|reason| reason.0),
+                inputs: Vec::new(),
+            });
+
+            if let (Some(stack_uuid), Some(account_id), Some(sequence_number), Some(qty)) =
+                (row.input_uuid, row.input_account_id, row.input_sequence_number, row.input_qty) {
+                stack.inputs.push(CraftInput {
+                    stack_uuid,
+                    account_id,
+                    sequence_number,
+                    qty,
+                });
+            }
+        }
+
+        Ok(build_lineage(&created, stack_uuid))
+    }
+
+}
+
+fn build_lineage(created: &HashMap<StackUuid, Created>, stack_uuid: StackUuid) -> Option<LineageNode> {
+    let stack = created.get(&stack_uuid)?;
+
+    let inputs = stack.inputs
+        .iter()
+        .filter_map(|input| Some((input.clone(), build_lineage(created, input.stack_uuid)?)))
+        .collect();
+
+    Some(LineageNode {
+        stack_uuid,
+        item_type: stack.item_type,
+        reason: stack.reason.clone(),
+        inputs,
+    })
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+use twox_hash::XxHash3_128;
+use crate::Error;
+
+const LOOT_DOMAIN: &[u8] = b"clusterium.loot_roll";
+
+/// What each lootable block type drops. The drop at a position only depends on the world seed,
+/// the position and the block type, e.g.
+///
+/// ```json
+/// {
+///     "world_seed": 42,
+///     "tables": [{ "block_type": 3, "entries": [{ "item_type": 7, "weight": 9, "min_qty": 1, "max_qty": 3 }] }]
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct LootTables {
+    world_seed: u64,
+    tables: HashMap<i32, LootTable>,
+}
+
+#[derive(Clone, Debug)]
+struct LootTable {
+    entries: Vec<LootEntry>,
+    total_weight: u64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct LootTablesFile {
+    world_seed: u64,
+    tables: Vec<LootTableFile>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct LootTableFile {
+    block_type: i32,
+    entries: Vec<LootEntry>,
+}
+
+/// Picked with probability `weight` over the table's total weight
+#[derive(Deserialize, Clone, Debug)]
+struct LootEntry {
+    item_type: i32,
+    weight: u64,
+    min_qty: u64,
+    max_qty: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LootDrop {
+    item_type: i32,
+    qty: u64,
+}
+
+impl LootDrop {
+
+    pub fn get_type(&self) -> i32 {
+        self.item_type
+    }
+
+    pub fn get_qty(&self) -> u64 {
+        self.qty
+    }
+
+}
+
+impl LootTable {
+
+    fn new(block_type: i32, entries: Vec<LootEntry>) -> Result<Self, Error> {
+        let invalid = |reason| Error::InvalidLootTable { block_type, reason };
+        let mut total_weight: u64 = 0;
+
+        for entry in &entries {
+            if entry.min_qty == 0 {
+                return Err(invalid("an entry can drop 0 items"));
+            }
+            if entry.min_qty > entry.max_qty {
+                return Err(invalid("an entry has a min_qty above its max_qty"));
+            }
+            total_weight = total_weight
+                .checked_add(entry.weight)
+                .ok_or_else(|| invalid("the weights add up to more than 64 bits"))?;
+        }
+
+        if total_weight == 0 {
+            return Err(invalid("the table has no weight"));
+        }
+
+        Ok(Self {
+            entries,
+            total_weight,
+        })
+    }
+
+}
+
+impl LootTables {
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let file: LootTablesFile = serde_json::from_str(json).map_err(Error::InvalidLootTablesFile)?;
+        let mut tables = HashMap::new();
+
+        for table in file.tables {
+            if tables.contains_key(&table.block_type) {
+                return Err(Error::InvalidLootTable { block_type: table.block_type, reason: "the block type has two tables" });
+            }
+            tables.insert(table.block_type, LootTable::new(table.block_type, table.entries)?);
+        }
+
+        Ok(Self {
+            world_seed: file.world_seed,
+            tables,
+        })
+    }
+
+    pub fn get_world_seed(&self) -> u64 {
+        self.world_seed
+    }
+
+    pub fn is_lootable(&self, block_type: i32) -> bool {
+        self.tables.contains_key(&block_type)
+    }
+
+    /// What breaking the block at the position drops, fails with `Error::NotLootable` if the
+    /// block type has no table
+    pub fn roll(&self, block_type: i32, x: i128, y: i128, z: i128, a: u32) -> Result<LootDrop, Error> {
+        let table = self.tables
+            .get(&block_type)
+            .ok_or(Error::NotLootable { block_type })?;
+
+        let mut bytes = Vec::with_capacity(LOOT_DOMAIN.len() + 56);
+        bytes.extend_from_slice(LOOT_DOMAIN);
+        bytes.extend_from_slice(&block_type.to_le_bytes());
+        bytes.extend_from_slice(&x.to_le_bytes());
+        bytes.extend_from_slice(&y.to_le_bytes());
+        bytes.extend_from_slice(&z.to_le_bytes());
+        bytes.extend_from_slice(&a.to_le_bytes());
+        let hash = XxHash3_128::oneshot_with_seed(self.world_seed, &bytes);
+
+        // Low half picks the entry, high half the quantity
+        let mut pick = hash as u64 % table.total_weight;
+        let mut picked = &table.entries[0];
+        for entry in &table.entries {
+            if pick < entry.weight {
+                picked = entry;
+                break;
+            }
+            pick -= entry.weight;
+        }
+
+        let span = (picked.max_qty - picked.min_qty) as u128 + 1;
+        let qty = picked.min_qty + ((hash >> 64) % span) as u64;
+
+        Ok(LootDrop {
+            item_type: picked.item_type,
+            qty,
+        })
+    }
+
+}
@@ -0,0 +1,395 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use sqlx::types::Json;
+use tokio::sync::{Mutex, MutexGuard};
+use crate::backend::{LedgerBackend, LedgerTx};
+use crate::chain::{ChainedEntry, GENESIS_HASH};
+use crate::{check_expected_version, checked_qty, AccountId, CraftInput, Error, LedgerEntry, Operation, Reason, ReceiptEntry, Stack, StackUuid};
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[derive(Clone)]
+struct LatestRow {
+    sequence_number: i32,
+    balance: i64,
+    item_type: i32,
+    entry_hash: [u8; 32],
+}
+
+#[derive(Clone)]
+struct FrozenRow {
+    reason: String,
+    expires_at: Option<i64>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    consumed: HashSet<StackUuid>,
+    latest: HashMap<(AccountId, StackUuid), LatestRow>,
+    composites: HashSet<(AccountId, StackUuid, i32)>,
+    ledger: Vec<LedgerEntry>,
+    frozen: HashMap<String, FrozenRow>,
+    craft_inputs: HashMap<StackUuid, Vec<CraftInput>>,
+}
+
+/// What to put back to roll a write back
+enum Undo {
+    Consumed(StackUuid),
+    Composite((AccountId, StackUuid, i32)),
+    Latest((AccountId, StackUuid), Option<LatestRow>),
+    Ledger,
+    CraftInputs(StackUuid, usize),
+}
+
+/// Keeps the whole ledger in process, meant for unit testing game logic without a database.
+/// Transactions are serialized and write to the state directly, dropping one without `commit`
+/// undoes its writes
+#[derive(Default)]
+pub struct MemoryBackend {
+    state: Mutex<MemoryState>,
+}
+
+pub struct MemoryTx<'a> {
+    state: MutexGuard<'a, MemoryState>,
+    undo: Vec<Undo>,
+}
+
+impl MemoryBackend {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get_entries(&self) -> Vec<LedgerEntry> {
+        self.state.lock().await.ledger.clone()
+    }
+
+    pub async fn get_balance(&self, account_id: &AccountId, stack_uuid: StackUuid) -> Option<i64> {
+        let state = self.state.lock().await;
+        state.latest
+            .get(&(account_id.clone(), stack_uuid))
+            .map(|latest| latest.balance)
+    }
+
+    /// Non-empty stacks of an account, same as what `InventoryManager::get_inventory` returns
+    pub async fn get_stacks(&self, account_id: &AccountId) -> Vec<Stack> {
+        let state = self.state.lock().await;
+        state.latest
+            .iter()
+            .filter(|((owner, _), latest)| owner == account_id && latest.balance > 0)
+            .map(|((_, stack_uuid), latest)| Stack {
+                stack_uuid: *stack_uuid,
+                sequence_number: latest.sequence_number,
+                balance: latest.balance,
+                item_type: latest.item_type,
+            })
+            .collect()
+    }
+
+    /// Same as `InventoryManager::get_craft_inputs`
+    pub async fn get_craft_inputs(&self, output_uuid: StackUuid) -> Vec<CraftInput> {
+        let state = self.state.lock().await;
+        state.craft_inputs
+            .get(&output_uuid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+}
+
+impl LedgerBackend for MemoryBackend {
+
+    type Tx<'a> = MemoryTx<'a>;
+
+    async fn begin(&self) -> Result<Self::Tx<'_>, Error> {
+        Ok(MemoryTx {
+            state: self.state.lock().await,
+            undo: Vec::new(),
+        })
+    }
+
+    async fn freeze_account(&self, account_id: &AccountId, reason: &str, expires_at: Option<i64>) -> Result<(), Error> {
+        let mut state = self.state.lock().await;
+        state.frozen.insert(account_id.to_string(), FrozenRow {
+            reason: reason.to_string(),
+            expires_at,
+        });
+        Ok(())
+    }
+
+    async fn unfreeze_account(&self, account_id: &AccountId) -> Result<(), Error> {
+        let mut state = self.state.lock().await;
+        state.frozen.remove(account_id.as_str());
+        Ok(())
+    }
+
+}
+
+impl MemoryState {
+
+    // Freezing an account freezes its briefcases too
+    fn ensure_not_frozen(&self, account_id: &AccountId) -> Result<(), Error> {
+        for frozen_id in [account_id.as_str(), account_id.get_owner()] {
+            let Some(frozen) = self.frozen.get(frozen_id) else {
+                continue;
+            };
+
+            match frozen.expires_at {
+                Some(expires_at) if expires_at <= unix_now() => {},
+                _ => return Err(Error::AccountFrozen {
+                    account_id: account_id.clone(),
+                    reason: frozen.reason.clone(),
+                    expires_at: frozen.expires_at,
+                }),
+            }
+        }
+
+        Ok(())
+    }
+
+}
+
+impl MemoryTx<'_> {
+
+    #[allow(clippy::too_many_arguments)]
+    fn append(&mut self, account_id: &AccountId, stack_uuid: StackUuid, sequence_number: i32, qty: i64, balance: i64, item_type: i32, operation: Operation, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+
+        // Same as the composite exclusion constraint of the ledger table
+        let composite = (account_id.clone(), stack_uuid, sequence_number);
+        if !self.state.composites.insert(composite.clone()) {
+            return Err(Error::SequenceConflict {
+                account_id: account_id.clone(),
+                stack_uuid,
+                sequence_number,
+            });
+        }
+
+        self.undo.push(Undo::Composite(composite));
+
+        let prev_hash = self.state.latest
+            .get(&(account_id.clone(), stack_uuid))
+            .map_or(GENESIS_HASH, |latest| latest.entry_hash);
+        let entry_hash = ChainedEntry {
+            account_id,
+            stack_uuid,
+            sequence_number,
+            qty,
+            balance,
+            item_type,
+            operation,
+            reason,
+        }.hash(&prev_hash);
+
+        let key = self.state.ledger.len() as i64 + 1;
+        self.state.ledger.push(LedgerEntry {
+            key,
+            account_id: account_id.clone(),
+            stack_uuid,
+            sequence_number,
+            qty,
+            balance,
+            item_type,
+            operation,
+            reason: reason.cloned().map(Json),
+            entry_hash: Some(entry_hash.to_vec()),
+            created_at: unix_now(),
+        });
+
+        self.undo.push(Undo::Ledger);
+
+        let previous = self.state.latest.insert((account_id.clone(), stack_uuid), LatestRow {
+            sequence_number,
+            balance,
+            item_type,
+            entry_hash,
+        });
+        self.undo.push(Undo::Latest((account_id.clone(), stack_uuid), previous));
+
+        Ok(ReceiptEntry {
+            key,
+            account_id: account_id.clone(),
+            stack_uuid,
+            sequence_number,
+            qty,
+            balance,
+            item_type,
+            operation,
+            entry_hash: entry_hash.to_vec(),
+        })
+    }
+
+    fn create_stack(&mut self, stack_uuid: StackUuid, item_type: i32, expected_sequence_number: Option<i32>, qty: u64, account_id: &AccountId, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+
+        let actual = self.state.latest
+            .get(&(account_id.clone(), stack_uuid))
+            .map(|latest| latest.sequence_number);
+        check_expected_version(expected_sequence_number, actual, account_id, stack_uuid)?;
+
+        if !self.state.consumed.insert(stack_uuid) {
+            return Err(Error::AlreadyConsumed { stack_uuid });
+        }
+        self.undo.push(Undo::Consumed(stack_uuid));
+
+        let qty = checked_qty(qty)?;
+        self.append(account_id, stack_uuid, 0, qty, qty, item_type, Operation::Create, reason)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn debit_stack(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, operation: Operation, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+
+        let qty = checked_qty(qty)?;
+        let latest = self.state.latest
+            .get(&(account_id.clone(), stack_uuid))
+            .cloned()
+            .ok_or_else(|| Error::StackNotFound {
+                account_id: account_id.clone(),
+                stack_uuid,
+            })?;
+
+        if let Some(expected) = expected_sequence_number
+            && expected != latest.sequence_number {
+            return Err(Error::StaleVersion {
+                account_id: account_id.clone(),
+                stack_uuid,
+                expected,
+                actual: latest.sequence_number,
+            });
+        }
+
+        if expected_item_type != latest.item_type {
+            return Err(Error::ItemTypeMismatch {
+                account_id: account_id.clone(),
+                stack_uuid,
+                expected: expected_item_type,
+                actual: latest.item_type,
+            });
+        }
+
+        if qty > latest.balance {
+            return Err(Error::NotEnoughBalance {
+                account_id: account_id.clone(),
+                stack_uuid,
+                qty,
+                balance: latest.balance,
+            });
+        }
+
+        self.append(account_id, stack_uuid, latest.sequence_number + 1, -qty, latest.balance - qty, latest.item_type, operation, reason)
+    }
+
+    fn credit_stack(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+
+        let qty = checked_qty(qty)?;
+
+        match self.state.latest.get(&(recipient_id.clone(), stack_uuid)).cloned() {
+            Some(latest) => {
+                check_expected_version(expected_sequence_number, Some(latest.sequence_number), recipient_id, stack_uuid)?;
+                let balance = latest.balance
+                    .checked_add(qty)
+                    .ok_or_else(|| Error::BalanceOverflow {
+                        account_id: recipient_id.clone(),
+                        stack_uuid,
+                        qty,
+                        balance: latest.balance,
+                    })?;
+                self.append(recipient_id, stack_uuid, latest.sequence_number + 1, qty, balance, latest.item_type, Operation::SplitIn, reason)
+            },
+            None => {
+                self.append(recipient_id, stack_uuid, 0, qty, qty, expected_item_type, Operation::SplitIn, reason)
+            },
+        }
+    }
+
+}
+
+impl Drop for MemoryTx<'_> {
+
+    fn drop(&mut self) {
+        while let Some(undo) = self.undo.pop() {
+            match undo {
+                Undo::Consumed(stack_uuid) => {
+                    self.state.consumed.remove(&stack_uuid);
+                },
+                Undo::Composite(composite) => {
+                    self.state.composites.remove(&composite);
+                },
+                Undo::Latest(key, Some(previous)) => {
+                    self.state.latest.insert(key, previous);
+                },
+                Undo::Latest(key, None) => {
+                    self.state.latest.remove(&key);
+                },
+                Undo::Ledger => {
+                    self.state.ledger.pop();
+                },
+                Undo::CraftInputs(output_uuid, len) => {
+                    if len == 0 {
+                        self.state.craft_inputs.remove(&output_uuid);
+                    } else if let Some(inputs) = self.state.craft_inputs.get_mut(&output_uuid) {
+                        inputs.truncate(len);
+                    }
+                },
+            }
+        }
+    }
+
+}
+
+impl LedgerTx for MemoryTx<'_> {
+
+    async fn create(&mut self, stack_uuid: StackUuid, item_type: i32, expected_sequence_number: Option<i32>, qty: u64, account_id: &AccountId, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        self.create_stack(stack_uuid, item_type, expected_sequence_number, qty, account_id, reason)
+    }
+
+    async fn destroy(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        self.state.ensure_not_frozen(account_id)?;
+        self.debit_stack(stack_uuid, expected_item_type, expected_sequence_number, account_id, qty, Operation::Destroy, reason)
+    }
+
+    async fn admin_destroy(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, account_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<ReceiptEntry, Error> {
+        self.debit_stack(stack_uuid, expected_item_type, expected_sequence_number, account_id, qty, Operation::Destroy, reason)
+    }
+
+    async fn split(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<(ReceiptEntry, ReceiptEntry), Error> {
+        self.state.ensure_not_frozen(sender_id)?;
+        let debit = self.debit_stack(stack_uuid, expected_item_type, expected_sequence_number, sender_id, qty, Operation::SplitOut, reason)?;
+        let credit = self.credit_stack(stack_uuid, expected_item_type, expected_recipient_sequence_number, recipient_id, qty, reason)?;
+        Ok((debit, credit))
+    }
+
+    async fn admin_split(&mut self, stack_uuid: StackUuid, expected_item_type: i32, expected_sequence_number: Option<i32>, expected_recipient_sequence_number: Option<i32>, sender_id: &AccountId, recipient_id: &AccountId, qty: u64, reason: Option<&Reason>) -> Result<(ReceiptEntry, ReceiptEntry), Error> {
+        let debit = self.debit_stack(stack_uuid, expected_item_type, expected_sequence_number, sender_id, qty, Operation::SplitOut, reason)?;
+        let credit = self.credit_stack(stack_uuid, expected_item_type, expected_recipient_sequence_number, recipient_id, qty, reason)?;
+        Ok((debit, credit))
+    }
+
+    async fn lock_stack(&mut self, account_id: &AccountId, stack_uuid: StackUuid) -> Result<Option<Stack>, Error> {
+        let stack = self.state.latest
+            .get(&(account_id.clone(), stack_uuid))
+            .map(|latest| Stack {
+                stack_uuid,
+                sequence_number: latest.sequence_number,
+                balance: latest.balance,
+                item_type: latest.item_type,
+            });
+        Ok(stack)
+    }
+
+    async fn record_craft(&mut self, output_uuid: StackUuid, inputs: &[CraftInput]) -> Result<(), Error> {
+        let craft_inputs = self.state.craft_inputs.entry(output_uuid).or_default();
+        self.undo.push(Undo::CraftInputs(output_uuid, craft_inputs.len()));
+        craft_inputs.extend_from_slice(inputs);
+        Ok(())
+    }
+
+    async fn commit(mut self) -> Result<(), Error> {
+        self.undo.clear();
+        Ok(())
+    }
+
+}
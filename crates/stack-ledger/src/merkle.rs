@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgExecutor, Postgres, Transaction};
+use crate::keys::KeyInput;
+use crate::{AccountId, InventoryManager, Stack, StackUuid};
+
+const LEAF_DOMAIN: &[u8] = b"clusterium.inventory_leaf";
+
+/// Root of an account without any `latest` rows
+pub const EMPTY_ROOT: [u8; 32] = [0; 32];
+
+/// Leaf of a `latest` row: SHA-256 over a 0x00 byte and the domain, key, stack uuid, sequence
+/// number, balance, item type and entry hash (empty for unchained stacks), each prefixed with its
+/// u32 LE length. The entry hash is the head of the stack's chain, so the root anchors all of them
+pub fn compute_leaf_hash(latest_key: &[u8], stack_uuid: StackUuid, sequence_number: i32, balance: i64, item_type: i32, entry_hash: &[u8]) -> [u8; 32] {
+    let input = KeyInput::with_domain(LEAF_DOMAIN)
+        .field(latest_key)
+        .field(&stack_uuid.to_le_bytes())
+        .field(&sequence_number.to_le_bytes())
+        .field(&balance.to_le_bytes())
+        .field(&item_type.to_le_bytes())
+        .field(entry_hash);
+
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(input.as_bytes());
+    hasher.finalize().into()
+}
+
+/// SHA-256 over a 0x01 byte and both children
+fn compute_node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// A node without a sibling moves up a level unchanged
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => compute_node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn compute_levels(leaves: &[[u8; 32]]) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaves.to_vec()];
+    while levels[levels.len() - 1].len() > 1 {
+        levels.push(next_level(&levels[levels.len() - 1]));
+    }
+    levels
+}
+
+fn compute_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// Root over the `latest` rows of one account given as (key, leaf hash), in any order. Leaves are
+/// sorted by key bytes, so the root of an `export_latest` dump can be checked against
+/// `InventoryManager::get_commitment`
+pub fn compute_inventory_root(mut leaves: Vec<(Vec<u8>, [u8; 32])>) -> [u8; 32] {
+    leaves.sort_unstable_by(|left, right| left.0.cmp(&right.0));
+    let hashes: Vec<[u8; 32]> = leaves.into_iter().map(|(_, hash)| hash).collect();
+    compute_root(&hashes)
+}
+
+fn compute_siblings(leaves: &[[u8; 32]], mut index: usize) -> Vec<[u8; 32]> {
+    let mut siblings = Vec::new();
+    let mut level = leaves.to_vec();
+
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            siblings.push(level[sibling]);
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+
+    siblings
+}
+
+// (level, position) of the siblings on the path from the leaf to the root
+fn compute_sibling_positions(mut index: usize, mut level_len: usize) -> Vec<(i16, i32)> {
+    let mut positions = Vec::new();
+    let mut level = 0;
+
+    while level_len > 1 {
+        let sibling = index ^ 1;
+        if sibling < level_len {
+            positions.push((level, sibling as i32));
+        }
+        index /= 2;
+        level_len = level_len.div_ceil(2);
+        level += 1;
+    }
+
+    positions
+}
+
+fn to_hash(bytes: &[u8]) -> Result<[u8; 32], sqlx::Error> {
+    <[u8; 32]>::try_from(bytes).map_err(|_| sqlx::Error::Decode("stored Merkle hash is not 32 bytes".into()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InventoryCommitment {
+    root: [u8; 32],
+    leaf_count: usize,
+}
+
+impl InventoryCommitment {
+
+    pub fn get_root(&self) -> &[u8; 32] {
+        &self.root
+    }
+
+    pub fn get_leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+}
+
+/// Proves a stack is part of an `InventoryCommitment`, can be checked without the database
+#[derive(Clone, Debug)]
+pub struct InclusionProof {
+    stack: Stack,
+    latest_key: Vec<u8>,
+    entry_hash: Option<Vec<u8>>,
+    index: usize,
+    leaf_count: usize,
+    siblings: Vec<[u8; 32]>,
+}
+
+impl InclusionProof {
+
+    pub fn get_stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn get_latest_key(&self) -> &[u8] {
+        &self.latest_key
+    }
+
+    /// Head of the stack's hash chain, `None` for stacks written before the chain existed
+    pub fn get_entry_hash(&self) -> Option<&[u8]> {
+        self.entry_hash.as_deref()
+    }
+
+    /// Position of the leaf among the account's `latest` rows sorted by key
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Sibling hashes from the leaf up, levels where the node had no sibling are skipped
+    pub fn get_siblings(&self) -> &[[u8; 32]] {
+        &self.siblings
+    }
+
+    pub fn verify(&self, root: &[u8; 32]) -> bool {
+        let mut hash = compute_leaf_hash(&self.latest_key, self.stack.stack_uuid, self.stack.sequence_number, self.stack.balance, self.stack.item_type, self.entry_hash.as_deref().unwrap_or_default());
+        let mut siblings = self.siblings.iter();
+        let mut index = self.index;
+        let mut level_len = self.leaf_count;
+
+        if index >= level_len {
+            return false;
+        }
+
+        while level_len > 1 {
+            if index % 2 == 1 {
+                let Some(sibling) = siblings.next() else {
+                    return false;
+                };
+                hash = compute_node_hash(sibling, &hash);
+            } else if index + 1 < level_len {
+                let Some(sibling) = siblings.next() else {
+                    return false;
+                };
+                hash = compute_node_hash(&hash, sibling);
+            }
+            index /= 2;
+            level_len = level_len.div_ceil(2);
+        }
+
+        siblings.next().is_none() && &hash == root
+    }
+
+}
+
+struct Leaf {
+    key: Vec<u8>,
+    stack: Stack,
+    entry_hash: Option<Vec<u8>>,
+    hash: [u8; 32],
+}
+
+impl Leaf {
+
+    fn compute_hash(&self) -> [u8; 32] {
+        compute_leaf_hash(&self.key, self.stack.stack_uuid, self.stack.sequence_number, self.stack.balance, self.stack.item_type, self.entry_hash.as_deref().unwrap_or_default())
+    }
+
+}
+
+async fn fetch_leaves<'e, E: PgExecutor<'e>>(executor: E, account_id: &AccountId) -> Result<Vec<Leaf>, sqlx::Error> {
+
+    let rows = sqlx::query!(
+        r#"SELECT key, stack_uuid AS "stack_uuid: StackUuid", sequence_number, balance, item_type, entry_hash, leaf_hash
+        FROM latest
+        WHERE account_id = $1
+        ORDER BY key;"#,
+        account_id as &AccountId)
+        .fetch_all(executor)
+        .await?;
+
+    let leaves = rows
+        .into_iter()
+        .map(|row| {
+            let mut leaf = Leaf {
+                key: row.key,
+                stack: Stack {
+                    stack_uuid: row.stack_uuid,
+                    sequence_number: row.sequence_number,
+                    balance: row.balance,
+                    item_type: row.item_type,
+                },
+                entry_hash: row.entry_hash,
+                hash: EMPTY_ROOT,
+            };
+            // Rows written before leaves were stored get theirs computed here
+            leaf.hash = row.leaf_hash
+                .as_deref()
+                .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+                .unwrap_or_else(|| leaf.compute_hash());
+            leaf
+        })
+        .collect();
+
+    Ok(leaves)
+}
+
+async fn fetch_nodes<'e, E: PgExecutor<'e>>(executor: E, account_id: &AccountId, positions: &[(i16, i32)]) -> Result<HashMap<(i16, i32), [u8; 32]>, sqlx::Error> {
+
+    let (levels, positions): (Vec<i16>, Vec<i32>) = positions.iter().copied().unzip();
+    let rows = sqlx::query!(
+        r#"SELECT level, position, hash
+        FROM inventory_merkle_nodes
+        WHERE account_id = $1 AND (level, position) IN (SELECT * FROM UNNEST($2::SMALLINT[], $3::INTEGER[]));"#,
+        account_id as &AccountId,
+        &levels,
+        &positions)
+        .fetch_all(executor)
+        .await?;
+
+    rows.into_iter()
+        .map(|row| Ok(((row.level, row.position), to_hash(&row.hash)?)))
+        .collect()
+}
+
+async fn store_nodes(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, nodes: &[(i16, i32, [u8; 32])]) -> Result<(), sqlx::Error> {
+
+    let levels: Vec<i16> = nodes.iter().map(|node| node.0).collect();
+    let positions: Vec<i32> = nodes.iter().map(|node| node.1).collect();
+    let hashes: Vec<Vec<u8>> = nodes.iter().map(|node| node.2.to_vec()).collect();
+
+    sqlx::query!(
+        r#"INSERT INTO inventory_merkle_nodes (account_id, level, position, hash)
+        SELECT $1, * FROM UNNEST($2::SMALLINT[], $3::INTEGER[], $4::BYTEA[])
+        ON CONFLICT ON CONSTRAINT uq_inventory_merkle_nodes_position DO UPDATE SET hash = EXCLUDED.hash;"#,
+        account_id as &AccountId,
+        &levels,
+        &positions,
+        &hashes)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn store_commitment(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, root: &[u8; 32], leaf_count: usize) -> Result<(), sqlx::Error> {
+
+    sqlx::query!(
+        r#"UPDATE inventory_commitments
+        SET root = $2, leaf_count = $3
+        WHERE account_id = $1;"#,
+        account_id as &AccountId,
+        root.as_slice(),
+        leaf_count as i32)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Locks the stored tree of the account until the transaction ends and returns its leaf count,
+/// `None` for accounts that didn't have one yet
+async fn lock_tree(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId) -> Result<Option<usize>, sqlx::Error> {
+
+    // Only the transaction that inserts the row builds the tree, the others wait for it
+    let inserted = sqlx::query!(
+        r#"INSERT INTO inventory_commitments (account_id, root, leaf_count)
+        VALUES ($1, $2, 0)
+        ON CONFLICT ON CONSTRAINT uq_inventory_commitments_account_id DO NOTHING
+        RETURNING leaf_count;"#,
+        account_id as &AccountId,
+        EMPTY_ROOT.as_slice())
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    if inserted.is_some() {
+        return Ok(None);
+    }
+
+    let leaf_count = sqlx::query_scalar!(
+        r#"SELECT leaf_count FROM inventory_commitments
+        WHERE account_id = $1
+        FOR UPDATE;"#,
+        account_id as &AccountId)
+        .fetch_one(&mut **tx)
+        .await?;
+
+    Ok(Some(leaf_count as usize))
+}
+
+// Replaces every stored node of the account with the tree of its current `latest` rows
+async fn build_tree(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId) -> Result<(), sqlx::Error> {
+
+    let leaves: Vec<[u8; 32]> = fetch_leaves(&mut **tx, account_id).await?
+        .iter()
+        .map(|leaf| leaf.hash)
+        .collect();
+
+    sqlx::query!(
+        r#"DELETE FROM inventory_merkle_nodes WHERE account_id = $1;"#,
+        account_id as &AccountId)
+        .execute(&mut **tx)
+        .await?;
+
+    if !leaves.is_empty() {
+        let nodes: Vec<(i16, i32, [u8; 32])> = compute_levels(&leaves)
+            .iter()
+            .enumerate()
+            .flat_map(|(level, hashes)| {
+                hashes
+                    .iter()
+                    .enumerate()
+                    .map(move |(position, hash)| (level as i16, position as i32, *hash))
+            })
+            .collect();
+        store_nodes(tx, account_id, &nodes).await?;
+    }
+
+    store_commitment(tx, account_id, &compute_root(&leaves), leaves.len()).await
+}
+
+// Leaves are sorted by key, so a row's position is the number of rows of the account before it
+async fn fetch_leaf_index<'e, E: PgExecutor<'e>>(executor: E, account_id: &AccountId, latest_key: &[u8]) -> Result<usize, sqlx::Error> {
+
+    let leaf_index = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM latest
+        WHERE account_id = $1 AND key < $2;"#,
+        account_id as &AccountId,
+        latest_key)
+        .fetch_one(executor)
+        .await?;
+
+    Ok(leaf_index as usize)
+}
+
+// Recomputes the path from the leaf to the root, the siblings on it are the only other nodes read
+async fn write_leaf(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, leaf_index: usize, leaf_count: usize, leaf_hash: [u8; 32]) -> Result<(), sqlx::Error> {
+
+    let siblings = fetch_nodes(&mut **tx, account_id, &compute_sibling_positions(leaf_index, leaf_count)).await?;
+
+    let mut hash = leaf_hash;
+    let mut index = leaf_index;
+    let mut level_len = leaf_count;
+    let mut level: i16 = 0;
+    let mut path = vec![(level, index as i32, hash)];
+
+    while level_len > 1 {
+        let sibling = index ^ 1;
+        if sibling < level_len {
+            let sibling_hash = siblings
+                .get(&(level, sibling as i32))
+                .ok_or(sqlx::Error::RowNotFound)?;
+            hash = if index % 2 == 1 {
+                compute_node_hash(sibling_hash, &hash)
+            } else {
+                compute_node_hash(&hash, sibling_hash)
+            };
+        }
+        index /= 2;
+        level_len = level_len.div_ceil(2);
+        level += 1;
+        path.push((level, index as i32, hash));
+    }
+
+    store_nodes(tx, account_id, &path).await?;
+    store_commitment(tx, account_id, &hash, leaf_count).await
+}
+
+/// Rebuilds the account's tree after `latest` rows were added to it or their keys changed, both
+/// move other leaves. Call it once the rows are written
+pub(crate) async fn rebuild_tree(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId) -> Result<(), sqlx::Error> {
+    lock_tree(tx, account_id).await?;
+    build_tree(tx, account_id).await
+}
+
+/// Replaces the leaf of a `latest` row whose key didn't change, only its path to the root is
+/// written. Call it once the row is written
+pub(crate) async fn set_leaf(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, latest_key: &[u8], leaf_hash: [u8; 32]) -> Result<(), sqlx::Error> {
+
+    let Some(leaf_count) = lock_tree(tx, account_id).await? else {
+        return build_tree(tx, account_id).await;
+    };
+
+    let leaf_index = fetch_leaf_index(&mut **tx, account_id, latest_key).await?;
+    write_leaf(tx, account_id, leaf_index, leaf_count, leaf_hash).await
+}
+
+/// Drops the stored tree of an account whose `latest` rows moved to `new_account_id` and builds
+/// the tree of the new id from them
+pub(crate) async fn move_tree(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, new_account_id: &AccountId) -> Result<(), sqlx::Error> {
+
+    sqlx::query!(
+        r#"DELETE FROM inventory_merkle_nodes WHERE account_id = $1;"#,
+        account_id as &AccountId)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!(
+        r#"DELETE FROM inventory_commitments WHERE account_id = $1;"#,
+        account_id as &AccountId)
+        .execute(&mut **tx)
+        .await?;
+
+    rebuild_tree(tx, new_account_id).await
+}
+
+/// Whether the stored root of the account still matches its `latest` rows, with every leaf
+/// recomputed from its row. Accounts without a stored tree have nothing to match
+pub(crate) async fn matches_commitment(conn: &mut PgConnection, account_id: &AccountId) -> Result<bool, sqlx::Error> {
+
+    let stored = sqlx::query!(
+        r#"SELECT root, leaf_count FROM inventory_commitments
+        WHERE account_id = $1;"#,
+        account_id as &AccountId)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    let Some(stored) = stored else {
+        return Ok(true);
+    };
+
+    let leaves: Vec<[u8; 32]> = fetch_leaves(&mut *conn, account_id).await?
+        .iter()
+        .map(Leaf::compute_hash)
+        .collect();
+
+    Ok(stored.leaf_count as usize == leaves.len() && stored.root == compute_root(&leaves))
+}
+
+impl InventoryManager {
+
+    /// Merkle root over every `latest` row of the account, sorted by key
+    pub async fn get_commitment(&self, account_id: &AccountId) -> Result<InventoryCommitment, sqlx::Error> {
+
+        let pool = self.read_pool(Some(account_id)).await;
+        let stored = sqlx::query!(
+            r#"SELECT root, leaf_count FROM inventory_commitments
+            WHERE account_id = $1;"#,
+            account_id as &AccountId)
+            .fetch_optional(pool)
+            .await?;
+
+        if let Some(stored) = stored {
+            return Ok(InventoryCommitment {
+                root: to_hash(&stored.root)?,
+                leaf_count: stored.leaf_count as usize,
+            });
+        }
+
+        let leaves: Vec<[u8; 32]> = fetch_leaves(pool, account_id).await?
+            .iter()
+            .map(|leaf| leaf.hash)
+            .collect();
+
+        Ok(InventoryCommitment {
+            root: compute_root(&leaves),
+            leaf_count: leaves.len(),
+        })
+    }
+
+    /// The proof and the commitment it verifies against, read from the same snapshot
+    pub async fn get_inclusion_proof(&self, account_id: &AccountId, stack_uuid: StackUuid) -> Result<Option<(InclusionProof, InventoryCommitment)>, sqlx::Error> {
+
+        let mut tx = self.read_pool(Some(account_id)).await.begin().await?;
+        sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY;")
+            .execute(&mut *tx)
+            .await?;
+
+        let stored = sqlx::query!(
+            r#"SELECT root, leaf_count FROM inventory_commitments
+            WHERE account_id = $1;"#,
+            account_id as &AccountId)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let Some(stored) = stored else {
+            return Self::compute_inclusion_proof(&mut tx, account_id, stack_uuid).await;
+        };
+
+        let leaf = sqlx::query!(
+            r#"SELECT key, sequence_number, balance, item_type, entry_hash
+            FROM latest
+            WHERE account_id = $1 AND stack_uuid = $2;"#,
+            account_id as &AccountId,
+            stack_uuid as StackUuid)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let Some(leaf) = leaf else {
+            return Ok(None);
+        };
+
+        let index = fetch_leaf_index(&mut *tx, account_id, &leaf.key).await?;
+        let leaf_count = stored.leaf_count as usize;
+        let positions = compute_sibling_positions(index, leaf_count);
+        let nodes = fetch_nodes(&mut *tx, account_id, &positions).await?;
+        let siblings = positions
+            .iter()
+            .map(|position| nodes.get(position).copied().ok_or(sqlx::Error::RowNotFound))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let commitment = InventoryCommitment {
+            root: to_hash(&stored.root)?,
+            leaf_count,
+        };
+        let proof = InclusionProof {
+            stack: Stack {
+                stack_uuid,
+                sequence_number: leaf.sequence_number,
+                balance: leaf.balance,
+                item_type: leaf.item_type,
+            },
+            latest_key: leaf.key,
+            entry_hash: leaf.entry_hash,
+            index,
+            leaf_count,
+            siblings,
+        };
+
+        Ok(Some((proof, commitment)))
+    }
+
+    // For accounts that weren't written since trees were stored
+    async fn compute_inclusion_proof(tx: &mut Transaction<'_, Postgres>, account_id: &AccountId, stack_uuid: StackUuid) -> Result<Option<(InclusionProof, InventoryCommitment)>, sqlx::Error> {
+
+        let leaves = fetch_leaves(&mut **tx, account_id).await?;
+        let Some(index) = leaves.iter().position(|leaf| leaf.stack.stack_uuid == stack_uuid) else {
+            return Ok(None);
+        };
+
+        let hashes: Vec<[u8; 32]> = leaves.iter().map(|leaf| leaf.hash).collect();
+        let commitment = InventoryCommitment {
+            root: compute_root(&hashes),
+            leaf_count: hashes.len(),
+        };
+
+        let leaf = &leaves[index];
+        let proof = InclusionProof {
+            stack: leaf.stack.clone(),
+            latest_key: leaf.key.clone(),
+            entry_hash: leaf.entry_hash.clone(),
+            index,
+            leaf_count: hashes.len(),
+            siblings: compute_siblings(&hashes, index),
+        };
+
+        Ok(Some((proof, commitment)))
+    }
+
+}
@@ -0,0 +1,184 @@
+use sqlx::PgPool;
+use crate::Error;
+
+/// Tables hash partitioned by account_id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionedTable {
+    Ledger,
+    Latest,
+}
+
+impl PartitionedTable {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PartitionedTable::Ledger => "ledger",
+            PartitionedTable::Latest => "latest",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    name: String,
+    modulus: i32,
+    remainder: i32,
+}
+
+impl Partition {
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_modulus(&self) -> i32 {
+        self.modulus
+    }
+
+    pub fn get_remainder(&self) -> i32 {
+        self.remainder
+    }
+
+}
+
+fn partition_name(table: PartitionedTable, modulus: i32, remainder: i32) -> String {
+    format!("{}_p{}_{}", table.as_str(), modulus, remainder)
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+// Postgres prints the bound as "FOR VALUES WITH (modulus 8, remainder 3)"
+fn parse_hash_bound(bound: &str) -> Option<(i32, i32)> {
+    let values = bound
+        .strip_prefix("FOR VALUES WITH (modulus ")?
+        .strip_suffix(')')?;
+    let (modulus, remainder) = values.split_once(", remainder ")?;
+    Some((modulus.parse().ok()?, remainder.parse().ok()?))
+}
+
+/// The migration already covers every account with 8 partitions per table, so new partitions
+/// either come from splitting one or fill the range of one that was detached
+pub struct PartitionManager {
+    pool: PgPool
+}
+
+impl PartitionManager {
+
+    pub async fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn get_partitions(&self, table: PartitionedTable) -> Result<Vec<Partition>, Error> {
+
+        let rows = sqlx::query!(
+            r#"SELECT child.relname AS "name!", pg_get_expr(child.relpartbound, child.oid) AS "bound!"
+            FROM pg_inherits
+            JOIN pg_class parent ON parent.oid = pg_inherits.inhparent
+            JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+            WHERE parent.relname = $1 AND parent.relkind = 'p'
+            ORDER BY child.relname;"#,
+            table.as_str())
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let (modulus, remainder) = parse_hash_bound(&row.bound)
+                    .ok_or_else(|| Error::InvalidPartitionBound {
+                        partition: row.name.clone(),
+                        bound: row.bound.clone(),
+                    })?;
+                Ok(Partition {
+                    name: row.name,
+                    modulus,
+                    remainder,
+                })
+            })
+            .collect()
+    }
+
+    /// Creates the partition and attaches it to `table`. Postgres rejects it if it overlaps with an
+    /// existing partition
+    pub async fn create_partition(&self, table: PartitionedTable, modulus: i32, remainder: i32) -> Result<Partition, Error> {
+
+        let name = partition_name(table, modulus, remainder);
+
+        sqlx::query(&format!(
+            "CREATE TABLE {} PARTITION OF {} FOR VALUES WITH (MODULUS {}, REMAINDER {});",
+            quote_ident(&name), table.as_str(), modulus, remainder))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(Partition {
+            name,
+            modulus,
+            remainder,
+        })
+    }
+
+    /// Splits a partition in two by doubling its modulus, moving its rows to the new partitions.
+    /// Everything runs in one transaction that locks `table`, so schedule it off-peak
+    pub async fn split_partition(&self, table: PartitionedTable, partition: &Partition) -> Result<[Partition; 2], Error> {
+
+        let modulus = partition.modulus * 2;
+        let remainders = [partition.remainder, partition.remainder + partition.modulus];
+        let old_name = quote_ident(&partition.name);
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(&format!("ALTER TABLE {} DETACH PARTITION {};", table.as_str(), old_name))
+            .execute(&mut *tx)
+            .await?;
+
+        let mut created = Vec::with_capacity(2);
+        for remainder in remainders {
+            let name = partition_name(table, modulus, remainder);
+
+            sqlx::query(&format!(
+                "CREATE TABLE {} PARTITION OF {} FOR VALUES WITH (MODULUS {}, REMAINDER {});",
+                quote_ident(&name), table.as_str(), modulus, remainder))
+                .execute(&mut *tx)
+                .await?;
+
+            created.push(Partition {
+                name,
+                modulus,
+                remainder,
+            });
+        }
+
+        // By name, a partition that was attached instead of created can have its columns in
+        // another order than the parent
+        let columns = sqlx::query_scalar!(
+            r#"SELECT attname::TEXT AS "name!" FROM pg_attribute
+            WHERE attrelid = $1::TEXT::regclass AND attnum > 0 AND NOT attisdropped
+            ORDER BY attnum;"#,
+            table.as_str())
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|column| quote_ident(column))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // Rows are routed to the new partitions through the parent, keeping the ledger keys
+        sqlx::query(&format!(
+            "INSERT INTO {} ({}) OVERRIDING SYSTEM VALUE SELECT {} FROM {};",
+            table.as_str(), columns, columns, old_name))
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(&format!("DROP TABLE {};", old_name))
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        let [low, high]: [Partition; 2] = created
+            .try_into()
+            .expect("two partitions were created");
+        Ok([low, high])
+    }
+
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeSet;
+use crate::keys::{compute_composite_key_bytes, compute_latest_key, CURRENT_KEY_VERSION};
+use crate::merkle::{compute_leaf_hash, rebuild_tree};
+use crate::{AccountId, Error, StackLedger, StackUuid};
+
+impl StackLedger {
+
+    /// Moves up to `batch_size` legacy (V0) rows of `latest`, `ledger` and `ledger_archive` to the
+    /// current key version, together with the copies of the keys in `inventories` and `stacks`.
+    /// Can run while the server is live, call it until it returns 0
+    pub async fn rekey_batch(&self, batch_size: i64) -> Result<u64, Error> {
+        let latest_rows = self.rekey_latest(batch_size).await?;
+        let ledger_rows = self.rekey_ledger(batch_size).await?;
+        let archive_rows = self.rekey_archive(batch_size).await?;
+        Ok(latest_rows + ledger_rows + archive_rows)
+    }
+
+    async fn rekey_latest(&self, batch_size: i64) -> Result<u64, Error> {
+
+        let mut tx = self.backend.begin().await?;
+
+        // Rows locked by a running debit are picked up by a later batch
+        let rows = sqlx::query!(
+            r#"SELECT key, account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number, balance, item_type, entry_hash
+            FROM latest
+            WHERE key_version = 0
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED;"#,
+            batch_size)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for row in &rows {
+
+            let new_key_bytes = compute_latest_key(CURRENT_KEY_VERSION, &row.account_id, row.stack_uuid).to_le_bytes();
+            let leaf_hash = compute_leaf_hash(&new_key_bytes, row.stack_uuid, row.sequence_number, row.balance, row.item_type, row.entry_hash.as_deref().unwrap_or_default());
+
+            sqlx::query!(
+                r#"UPDATE latest
+                SET key = $1, key_version = $2, leaf_hash = $5
+                WHERE key = $3 AND account_id = $4;"#,
+                new_key_bytes.as_slice(),
+                CURRENT_KEY_VERSION as i16,
+                row.key,
+                &row.account_id as &AccountId,
+                leaf_hash.as_slice())
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query!(
+                r#"UPDATE inventories
+                SET latest_keys = array_replace(latest_keys, $1, $2)
+                WHERE account_id = $3;"#,
+                row.key,
+                new_key_bytes.as_slice(),
+                &row.account_id as &AccountId)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query!(
+                r#"UPDATE stacks
+                SET latest_keys = array_replace(latest_keys, $1, $2)
+                WHERE stack_uuid = $3;"#,
+                row.key,
+                new_key_bytes.as_slice(),
+                row.stack_uuid as StackUuid)
+                .execute(&mut *tx)
+                .await?;
+
+        }
+
+        // New keys sort differently. In order, so batches running at once don't deadlock
+        let account_ids: BTreeSet<&AccountId> = rows.iter().map(|row| &row.account_id).collect();
+        for account_id in &account_ids {
+            rebuild_tree(&mut tx, account_id).await?;
+        }
+
+        let account_ids: Vec<&AccountId> = account_ids.into_iter().collect();
+        self.commit(tx, &account_ids).await?;
+        Ok(rows.len() as u64)
+    }
+
+    async fn rekey_ledger(&self, batch_size: i64) -> Result<u64, Error> {
+
+        let mut tx = self.backend.begin().await?;
+
+        let rows = sqlx::query!(
+            r#"SELECT key, account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number
+            FROM ledger
+            WHERE key_version = 0
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED;"#,
+            batch_size)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for row in &rows {
+
+            let composite_key_bytes = compute_composite_key_bytes(CURRENT_KEY_VERSION, &row.account_id, row.stack_uuid, row.sequence_number);
+
+            sqlx::query!(
+                r#"UPDATE ledger
+                SET composite = $1, key_version = $2
+                WHERE key = $3 AND account_id = $4;"#,
+                composite_key_bytes.as_slice(),
+                CURRENT_KEY_VERSION as i16,
+                row.key,
+                &row.account_id as &AccountId)
+                .execute(&mut *tx)
+                .await?;
+
+        }
+
+        tx.commit().await?;
+        Ok(rows.len() as u64)
+    }
+
+    // Only erasures write archived rows, waiting for one is fine
+    async fn rekey_archive(&self, batch_size: i64) -> Result<u64, Error> {
+
+        let mut tx = self.backend.begin().await?;
+
+        let rows = sqlx::query!(
+            r#"SELECT key, account_id AS "account_id: AccountId", stack_uuid AS "stack_uuid: StackUuid", sequence_number
+            FROM ledger_archive
+            WHERE key_version = 0
+            LIMIT $1
+            FOR UPDATE;"#,
+            batch_size)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for row in &rows {
+
+            let composite_key_bytes = compute_composite_key_bytes(CURRENT_KEY_VERSION, &row.account_id, row.stack_uuid, row.sequence_number);
+
+            sqlx::query!(
+                r#"UPDATE ledger_archive
+                SET composite = $1, key_version = $2
+                WHERE key = $3 AND account_id = $4;"#,
+                composite_key_bytes.as_slice(),
+                CURRENT_KEY_VERSION as i16,
+                row.key,
+                &row.account_id as &AccountId)
+                .execute(&mut *tx)
+                .await?;
+
+        }
+
+        tx.commit().await?;
+        Ok(rows.len() as u64)
+    }
+
+}
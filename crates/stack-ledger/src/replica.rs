@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI64, Ordering};
+use sqlx::PgPool;
+use crate::{AccountId, InventoryManager};
+
+/// Read-only pool plus the WAL positions (in bytes) reads have to wait for
+pub(crate) struct ReadReplica {
+    pool: PgPool,
+    replayed_lsn: AtomicI64,
+    pending: Mutex<PendingWrites>,
+}
+
+#[derive(Default)]
+struct PendingWrites {
+    lsns: HashMap<AccountId, i64>,
+    latest_lsn: i64,
+    // Writes that may have committed but whose position isn't known yet
+    writing: HashMap<AccountId, usize>,
+    writes: usize,
+    unresolved: Vec<AccountId>,
+}
+
+impl PendingWrites {
+
+    fn resolve(&mut self, account_id: AccountId, lsn: i64) {
+        if let Some(writing) = self.writing.get_mut(&account_id) {
+            *writing -= 1;
+            if *writing == 0 {
+                self.writing.remove(&account_id);
+            }
+        }
+        self.writes -= 1;
+        let required = self.lsns.entry(account_id).or_default();
+        *required = (*required).max(lsn);
+        self.latest_lsn = self.latest_lsn.max(lsn);
+    }
+
+}
+
+/// Write started with `ReadReplica::start_write`. Dropping it without `finish` counts as finishing
+/// without a position, so a commit that never got to `finish` doesn't keep its accounts on the
+/// primary for good
+pub(crate) struct PendingWrite {
+    replica: Arc<ReadReplica>,
+    account_ids: Vec<AccountId>,
+}
+
+impl PendingWrite {
+
+    /// Call once the commit is done with the primary's position after it. Without one the write
+    /// stays in flight until a later write gets a position, which is past this commit too
+    pub(crate) fn finish(mut self, lsn: Option<i64>) {
+        let account_ids = std::mem::take(&mut self.account_ids);
+        self.replica.finish_write(account_ids, lsn);
+    }
+
+}
+
+impl Drop for PendingWrite {
+
+    fn drop(&mut self) {
+        if !self.account_ids.is_empty() {
+            let account_ids = std::mem::take(&mut self.account_ids);
+            self.replica.finish_write(account_ids, None);
+        }
+    }
+
+}
+
+impl ReadReplica {
+
+    fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            replayed_lsn: AtomicI64::new(0),
+            pending: Mutex::new(PendingWrites::default()),
+        }
+    }
+
+    /// Call before committing, reads of these accounts stay on the primary from then on
+    pub(crate) fn start_write(self: &Arc<Self>, account_ids: &[&AccountId]) -> PendingWrite {
+        let mut pending = self.pending.lock().unwrap();
+        for account_id in account_ids {
+            *pending.writing.entry((*account_id).clone()).or_default() += 1;
+            pending.writes += 1;
+        }
+
+        PendingWrite {
+            replica: self.clone(),
+            account_ids: account_ids.iter().map(|account_id| (*account_id).clone()).collect(),
+        }
+    }
+
+    fn finish_write(&self, account_ids: Vec<AccountId>, lsn: Option<i64>) {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(lsn) = lsn else {
+            pending.unresolved.extend(account_ids);
+            return;
+        };
+
+        let unresolved = std::mem::take(&mut pending.unresolved);
+        for account_id in account_ids.into_iter().chain(unresolved) {
+            pending.resolve(account_id, lsn);
+        }
+    }
+
+    // Reads that aren't for a single account wait for every write
+    fn get_required_lsn(&self, account_id: Option<&AccountId>) -> i64 {
+        let pending = self.pending.lock().unwrap();
+        match account_id {
+            Some(account_id) if pending.writing.contains_key(account_id) => i64::MAX,
+            Some(account_id) => pending.lsns.get(account_id).copied().unwrap_or(0),
+            None if pending.writes > 0 => i64::MAX,
+            None => pending.latest_lsn,
+        }
+    }
+
+    async fn has_replayed(&self, lsn: i64) -> bool {
+        if self.replayed_lsn.load(Ordering::Relaxed) >= lsn {
+            return true;
+        }
+        if lsn == i64::MAX {
+            return false;
+        }
+
+        // NULL when the pool isn't a standby, an unreachable replica counts as behind too
+        let replayed = sqlx::query_scalar!(
+            r#"SELECT (pg_last_wal_replay_lsn() - '0/0')::BIGINT;"#)
+            .fetch_one(&self.pool)
+            .await;
+        let Ok(Some(replayed)) = replayed else {
+            return false;
+        };
+
+        self.replayed_lsn.fetch_max(replayed, Ordering::Relaxed);
+        self.pending.lock().unwrap().lsns.retain(|_, required| *required > replayed);
+        replayed >= lsn
+    }
+
+}
+
+impl InventoryManager {
+
+    /// Sends reads to `replica` unless they could miss a write committed by a `StackLedger`
+    /// tracking this manager, see `get_write_tracker`
+    pub fn with_read_replica(mut self, replica: PgPool) -> Self {
+        self.replica = Some(Arc::new(ReadReplica::new(replica)));
+        self
+    }
+
+    /// Pool for a read of the account, or of any account with None
+    pub(crate) async fn read_pool(&self, account_id: Option<&AccountId>) -> &PgPool {
+
+        let Some(replica) = &self.replica else {
+            return &self.pool;
+        };
+
+        if replica.has_replayed(replica.get_required_lsn(account_id)).await {
+            &replica.pool
+        } else {
+            &self.pool
+        }
+    }
+
+}
@@ -0,0 +1,177 @@
+use std::collections::{BTreeSet, HashMap};
+use serde::Deserialize;
+use crate::keys::KeyInput;
+use crate::{compute_xyza_uuid, AccountId, Error, Reason, StackLedger, StackUuid};
+
+const SEED_DOMAIN: &[u8] = b"clusterium.seed_stack";
+
+/// Starting state of a shard, e.g.
+///
+/// ```json
+/// {
+///     "name": "eu-1",
+///     "accounts": [{ "account_id": "alice", "stacks": [{ "item_type": 7, "qty": 10 }] }],
+///     "drops": [{ "account_id": "xj9wka", "x": 1, "y": 2, "z": 3, "a": 0, "item_type": 7, "qty": 5 }]
+/// }
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+pub struct Seed {
+    name: String, // part of the uuid of every starting stack, so two seeds never collide
+    #[serde(default)]
+    accounts: Vec<SeedAccount>,
+    #[serde(default)]
+    drops: Vec<SeedDrop>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct SeedAccount {
+    account_id: AccountId,
+    #[serde(default)]
+    stacks: Vec<SeedStack>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct SeedStack {
+    item_type: i32,
+    qty: u64,
+}
+
+/// Takes the position's loot uuid, so `create_from_xyza` can't loot it afterwards
+#[derive(Deserialize, Clone, Debug)]
+struct SeedDrop {
+    account_id: AccountId,
+    x: i128,
+    y: i128,
+    z: i128,
+    a: u32,
+    item_type: i32,
+    qty: u64,
+}
+
+struct Provision<'a> {
+    stack_uuid: StackUuid,
+    item_type: i32,
+    qty: u64,
+    account_id: &'a AccountId,
+    reason: Reason,
+}
+
+// Stacks of the same type in an account are told apart by their position among that type, so
+// adding stacks of another type to a seed doesn't change any uuid
+fn compute_seed_uuid(seed: &str, account_id: &AccountId, item_type: i32, ordinal: u32) -> StackUuid {
+    let key = KeyInput::with_domain(SEED_DOMAIN)
+        .field(seed.as_bytes())
+        .field(account_id.as_bytes())
+        .field(&item_type.to_le_bytes())
+        .field(&ordinal.to_le_bytes())
+        .hash();
+
+    StackUuid::new(key)
+}
+
+impl Seed {
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_account_ids(&self) -> BTreeSet<&AccountId> {
+        self.accounts
+            .iter()
+            .map(|account| &account.account_id)
+            .chain(self.drops.iter().map(|drop| &drop.account_id))
+            .collect()
+    }
+
+    fn get_provisions(&self) -> Vec<Provision<'_>> {
+        let mut provisions = Vec::new();
+
+        for account in &self.accounts {
+            let mut ordinals: HashMap<i32, u32> = HashMap::new();
+            for stack in &account.stacks {
+                let ordinal = ordinals.entry(stack.item_type).or_default();
+                provisions.push(Provision {
+                    stack_uuid: compute_seed_uuid(&self.name, &account.account_id, stack.item_type, *ordinal),
+                    item_type: stack.item_type,
+                    qty: stack.qty,
+                    account_id: &account.account_id,
+                    reason: Reason::Seed { seed: self.name.clone() },
+                });
+                *ordinal += 1;
+            }
+        }
+
+        for drop in &self.drops {
+            provisions.push(Provision {
+                stack_uuid: compute_xyza_uuid(drop.x, drop.y, drop.z, drop.a),
+                item_type: drop.item_type,
+                qty: drop.qty,
+                account_id: &drop.account_id,
+                reason: Reason::Loot { x: drop.x, y: drop.y, z: drop.z, a: drop.a },
+            });
+        }
+
+        provisions
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SeedReport {
+    created: usize,
+    skipped: usize,
+}
+
+impl SeedReport {
+
+    pub fn get_created(&self) -> usize {
+        self.created
+    }
+
+    /// Stacks whose uuid was already consumed, by an earlier run or by a player looting the drop
+    pub fn get_skipped(&self) -> usize {
+        self.skipped
+    }
+
+}
+
+impl StackLedger {
+
+    /// Creates the seed's inventories and stacks in a single transaction. Stack uuids are derived
+    /// from the seed, so running it again only mints the stacks that were added to it since
+    pub async fn seed(&self, seed: &Seed) -> Result<SeedReport, Error> {
+
+        let provisions = seed.get_provisions();
+        let mut report = SeedReport::default();
+        let mut tx = self.backend.begin().await?;
+
+        let account_ids: Vec<&AccountId> = seed.get_account_ids().into_iter().collect();
+        for account_id in account_ids.iter().copied() {
+            sqlx::query!(
+                r#"INSERT INTO inventories (account_id)
+                VALUES ($1)
+                ON CONFLICT DO NOTHING;"#,
+                account_id as &AccountId)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        for provision in &provisions {
+            if StackLedger::is_consumed(&mut tx, provision.stack_uuid, Some(&provision.reason)).await? {
+                report.skipped += 1;
+                continue;
+            }
+
+            StackLedger::create(&mut tx, provision.stack_uuid, provision.item_type, None, provision.qty, provision.account_id, Some(&provision.reason)).await?;
+            report.created += 1;
+        }
+
+        self.commit(tx, &account_ids).await?;
+        Ok(report)
+    }
+
+}
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+use sqlx::PgPool;
+use crate::backend::{LedgerBackend, LedgerTx};
+use crate::cache::InventoryCache;
+use crate::replica::{PendingWrite, ReadReplica};
+use crate::{AccountId, Error, InventoryManager, StackLedger};
+
+/// Lets a `StackLedger` tell an `InventoryManager` in the same process about its writes as soon as
+/// they commit, instead of waiting for the notification from the database or the replica
+#[derive(Clone)]
+pub struct WriteTracker {
+    pool: PgPool,
+    cache: Option<Arc<InventoryCache>>,
+    replica: Option<Arc<ReadReplica>>,
+}
+
+/// Settles a tracked write when dropped, also when the future committing it is dropped halfway
+struct TrackedWrite {
+    cache: Option<Arc<InventoryCache>>,
+    replica: Option<PendingWrite>,
+    account_ids: Vec<AccountId>,
+}
+
+impl Drop for TrackedWrite {
+
+    fn drop(&mut self) {
+        if let Some(cache) = &self.cache {
+            for account_id in &self.account_ids {
+                cache.invalidate(account_id);
+            }
+        }
+    }
+
+}
+
+impl WriteTracker {
+
+    fn start_write(&self, account_ids: &[&AccountId]) -> TrackedWrite {
+        TrackedWrite {
+            cache: self.cache.clone(),
+            replica: self.replica.as_ref().map(|replica| replica.start_write(account_ids)),
+            account_ids: account_ids.iter().map(|account_id| (*account_id).clone()).collect(),
+        }
+    }
+
+    async fn finish_write(&self, mut write: TrackedWrite) {
+        if let Some(replica) = write.replica.take() {
+            // The commit is already flushed, so the current position is at or past it. Without it
+            // the accounts keep reading from the primary, the write itself went through
+            let lsn = sqlx::query_scalar!(
+                r#"SELECT (pg_current_wal_lsn() - '0/0')::BIGINT AS "lsn!";"#)
+                .fetch_one(&self.pool)
+                .await
+                .ok();
+            replica.finish(lsn);
+        }
+    }
+
+}
+
+impl InventoryManager {
+
+    /// Pass to `StackLedger::track_writes` once the manager is configured
+    pub fn get_write_tracker(&self) -> WriteTracker {
+        WriteTracker {
+            pool: self.pool.clone(),
+            cache: self.cache.clone(),
+            replica: self.replica.clone(),
+        }
+    }
+
+}
+
+impl<B: LedgerBackend> StackLedger<B> {
+
+    /// Every write the ledger commits is reported to the tracker's manager
+    pub fn track_writes(mut self, tracker: WriteTracker) -> Self {
+        self.trackers.push(tracker);
+        self
+    }
+
+    /// Commits a transaction that wrote to `account_ids`, their next reads through a tracked
+    /// manager see it. Use it for transactions passed to `InventoryActions::create_from_xyza`
+    pub async fn commit<T: LedgerTx>(&self, tx: T, account_ids: &[&AccountId]) -> Result<(), Error> {
+        let writes: Vec<_> = self.trackers.iter()
+            .map(|tracker| tracker.start_write(account_ids))
+            .collect();
+        // A failed commit may still have gone through, so it is reported the same way
+        let result = tx.commit().await;
+        for (tracker, write) in self.trackers.iter().zip(writes) {
+            tracker.finish_write(write).await;
+        }
+        result
+    }
+
+}
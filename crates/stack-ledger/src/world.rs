@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::future::Future;
+use tokio::sync::Mutex;
+use crate::Error;
+
+/// Blocks a `net_types::chunk::RelevantChunk` spans along x, y and z
+pub const RELEVANT_CHUNK_SIZE: [i128; 3] = [16, 16, 16];
+
+/// Blocks a `net_types::chunk::CommonChunk` spans along x, y and z, like
+/// `net_types::block::Block::get_common_chunk_id`
+pub const COMMON_CHUNK_SIZE: [i128; 3] = [32, 32, 64];
+
+const MAX_BREAK_ATTEMPTS: usize = 8;
+
+/// Authoritative blocks of the world, what the chunk store (`net_types::chunk::RelevantChunk`
+/// over `CommonChunk`) says is at a position. `create_from_xyza` only loots blocks read from here,
+/// `ChunkWorld` implements it over the server's relevant chunks
+pub trait WorldBlocks: Send + Sync {
+
+    /// Block type at the position, None for air
+    fn get_block(&self, x: i128, y: i128, z: i128, a: u32) -> impl Future<Output = Result<Option<i32>, Error>> + Send;
+
+    /// Replaces the block with air if it's still `block_type`, returns false if it changed
+    fn break_block(&self, x: i128, y: i128, z: i128, a: u32, block_type: i32) -> impl Future<Output = Result<bool, Error>> + Send;
+
+}
+
+/// Blocks kept in memory, every position not set is air
+#[derive(Default)]
+pub struct MemoryWorld {
+    blocks: Mutex<HashMap<(i128, i128, i128, u32), i32>>,
+}
+
+impl MemoryWorld {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn set_block(&self, x: i128, y: i128, z: i128, a: u32, block_type: i32) {
+        self.blocks.lock().await.insert((x, y, z, a), block_type);
+    }
+
+}
+
+impl WorldBlocks for MemoryWorld {
+
+    async fn get_block(&self, x: i128, y: i128, z: i128, a: u32) -> Result<Option<i32>, Error> {
+        Ok(self.blocks.lock().await.get(&(x, y, z, a)).copied())
+    }
+
+    async fn break_block(&self, x: i128, y: i128, z: i128, a: u32, block_type: i32) -> Result<bool, Error> {
+        let mut blocks = self.blocks.lock().await;
+        if blocks.get(&(x, y, z, a)) != Some(&block_type) {
+            return Ok(false);
+        }
+
+        blocks.remove(&(x, y, z, a));
+        Ok(true)
+    }
+
+}
+
+/// A chunk in chunk coordinates, block coordinates divided by the size of its kind of chunk and
+/// rounded down
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkPosition {
+    x: i128,
+    y: i128,
+    z: i128,
+    a: u32,
+}
+
+impl ChunkPosition {
+
+    fn new(size: [i128; 3], x: i128, y: i128, z: i128, a: u32) -> Self {
+        Self {
+            x: x.div_euclid(size[0]),
+            y: y.div_euclid(size[1]),
+            z: z.div_euclid(size[2]),
+            a,
+        }
+    }
+
+    pub fn get_x(&self) -> i128 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> i128 {
+        self.y
+    }
+
+    pub fn get_z(&self) -> i128 {
+        self.z
+    }
+
+    pub fn get_a(&self) -> u32 {
+        self.a
+    }
+
+}
+
+/// Position of a block in the flat array of the chunk of `size` holding it, x first, then y,
+/// then z. Whatever writes chunks has to lay them out the same way. A common chunk has more
+/// blocks than an i16 holds, its indices wrap around and are read back as u16
+pub fn compute_block_index(size: [i128; 3], x: i128, y: i128, z: i128) -> i16 {
+    let (x, y, z) = (x.rem_euclid(size[0]), y.rem_euclid(size[1]), z.rem_euclid(size[2]));
+    ((z * size[1] + y) * size[0] + x) as u16 as i16
+}
+
+// Inverse of compute_block_index, relative to the chunk's first block
+fn get_block_offset(size: [i128; 3], index: i16) -> (i128, i128, i128) {
+    let index = index as u16 as i128;
+    (index % size[0], index / size[0] % size[1], index / (size[0] * size[1]))
+}
+
+/// Same fields as a `RelevantChunk`: its non-air blocks as (index, block type) and the version
+/// it's optimistically locked with
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoredChunk {
+    blocks: Vec<(i16, i32)>,
+    version: i32,
+}
+
+impl StoredChunk {
+
+    pub fn new(blocks: Vec<(i16, i32)>, version: i32) -> Self {
+        Self { blocks, version }
+    }
+
+    pub fn get_blocks(&self) -> &[(i16, i32)] {
+        &self.blocks
+    }
+
+    pub fn get_version(&self) -> i32 {
+        self.version
+    }
+
+}
+
+/// Where the server keeps its `net_types::chunk::RelevantChunk`s and `CommonChunk`s. This crate
+/// can't depend on net_types, so the server implements it by converting `ChunkPosition` to its
+/// `ChunkId`
+pub trait ChunkStore: Send + Sync {
+
+    /// None if the relevant chunk was never written, its blocks are the common chunk's then
+    fn load_chunk(&self, position: ChunkPosition) -> impl Future<Output = Result<Option<StoredChunk>, Error>> + Send;
+
+    /// Non-air blocks of the common chunk, indexed with `COMMON_CHUNK_SIZE`. None if there's no
+    /// common chunk there, it only has air then
+    fn load_common_chunk(&self, position: ChunkPosition) -> impl Future<Output = Result<Option<Vec<(i16, i32)>>, Error>> + Send;
+
+    /// Replaces the relevant chunk's blocks and increments its version, unless the version isn't
+    /// `expected_version` anymore (0 for a chunk never written). Returns false in that case
+    fn store_chunk(&self, position: ChunkPosition, blocks: Vec<(i16, i32)>, expected_version: i32) -> impl Future<Output = Result<bool, Error>> + Send;
+
+}
+
+/// `WorldBlocks` over relevant chunks, falling back to the common chunk for relevant chunks never
+/// written. Breaking a block writes the whole relevant chunk, retries when its version changed
+/// under it and fails with `Error::World` if it keeps changing
+pub struct ChunkWorld<S> {
+    store: S,
+}
+
+impl<S: ChunkStore> ChunkWorld<S> {
+
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    pub fn get_store(&self) -> &S {
+        &self.store
+    }
+
+    // Blocks and version of the relevant chunk, taken from the common chunk if it was never
+    // written
+    async fn load_blocks(&self, position: ChunkPosition) -> Result<(Vec<(i16, i32)>, i32), Error> {
+        if let Some(chunk) = self.store.load_chunk(position).await? {
+            return Ok((chunk.blocks, chunk.version));
+        }
+
+        let [size_x, size_y, size_z] = RELEVANT_CHUNK_SIZE;
+        let (x, y, z) = (position.x * size_x, position.y * size_y, position.z * size_z);
+        let common_position = ChunkPosition::new(COMMON_CHUNK_SIZE, x, y, z, position.a);
+        let Some(common_blocks) = self.store.load_common_chunk(common_position).await? else {
+            return Ok((Vec::new(), 0));
+        };
+
+        let [common_x, common_y, common_z] = COMMON_CHUNK_SIZE;
+        let (origin_x, origin_y, origin_z) = (common_position.x * common_x, common_position.y * common_y, common_position.z * common_z);
+        let blocks = common_blocks
+            .into_iter()
+            .filter_map(|(index, block_type)| {
+                let (offset_x, offset_y, offset_z) = get_block_offset(COMMON_CHUNK_SIZE, index);
+                let (block_x, block_y, block_z) = (origin_x + offset_x, origin_y + offset_y, origin_z + offset_z);
+                (ChunkPosition::new(RELEVANT_CHUNK_SIZE, block_x, block_y, block_z, position.a) == position)
+                    .then(|| (compute_block_index(RELEVANT_CHUNK_SIZE, block_x, block_y, block_z), block_type))
+            })
+            .collect();
+        Ok((blocks, 0))
+    }
+
+}
+
+impl<S: ChunkStore> WorldBlocks for ChunkWorld<S> {
+
+    async fn get_block(&self, x: i128, y: i128, z: i128, a: u32) -> Result<Option<i32>, Error> {
+        let (blocks, _) = self.load_blocks(ChunkPosition::new(RELEVANT_CHUNK_SIZE, x, y, z, a)).await?;
+        let index = compute_block_index(RELEVANT_CHUNK_SIZE, x, y, z);
+        Ok(blocks.iter().find(|(block_index, _)| *block_index == index).map(|(_, block_type)| *block_type))
+    }
+
+    async fn break_block(&self, x: i128, y: i128, z: i128, a: u32, block_type: i32) -> Result<bool, Error> {
+        let position = ChunkPosition::new(RELEVANT_CHUNK_SIZE, x, y, z, a);
+        let index = compute_block_index(RELEVANT_CHUNK_SIZE, x, y, z);
+
+        for _ in 0..MAX_BREAK_ATTEMPTS {
+            let (blocks, version) = self.load_blocks(position).await?;
+            if !blocks.contains(&(index, block_type)) {
+                return Ok(false);
+            }
+
+            let blocks = blocks
+                .into_iter()
+                .filter(|(block_index, _)| *block_index != index)
+                .collect();
+            if self.store.store_chunk(position, blocks, version).await? {
+                return Ok(true);
+            }
+        }
+
+        Err(Error::World(format!("chunk {position:?} kept changing while breaking a block").into()))
+    }
+
+}
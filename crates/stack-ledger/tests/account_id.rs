@@ -0,0 +1,33 @@
+use stack_ledger::AccountId;
+
+#[test]
+fn every_account_has_room_for_its_briefcases() {
+    let owner = "p".repeat(52);
+    assert!(AccountId::new(&owner).is_ok());
+    assert!(AccountId::new(&"p".repeat(53)).is_err());
+
+    let briefcase = AccountId::new_briefcase(&AccountId::new(&owner).unwrap(), u32::MAX).unwrap();
+    assert_eq!(briefcase.as_str().len(), 64);
+    assert_eq!(briefcase.get_owner(), owner);
+}
+
+#[test]
+fn briefcase_suffix_needs_a_number() {
+    let briefcase: AccountId = "alice_b3".parse().unwrap();
+    assert_eq!(briefcase.get_owner(), "alice");
+    assert_eq!(briefcase.get_briefcase_number(), Some(3));
+    assert_eq!(AccountId::new("alice_bx").unwrap().get_owner(), "alice_bx");
+    assert_eq!(AccountId::new("alice_b").unwrap().get_owner(), "alice_b");
+    assert_eq!(AccountId::new("alice_b03").unwrap().get_owner(), "alice_b03");
+    assert!(format!("{}_b1", "p".repeat(53)).parse::<AccountId>().is_err());
+    assert!(AccountId::new("_b1").is_ok());
+}
+
+#[test]
+fn players_cant_be_named_like_briefcases() {
+    assert!(AccountId::new("bob_b2").is_err());
+    let briefcase = AccountId::new_briefcase(&AccountId::new("bob").unwrap(), 2).unwrap();
+    assert_eq!(briefcase.as_str(), "bob_b2");
+    assert!(AccountId::new_briefcase(&briefcase, 1).is_err());
+    assert!("bob_b2_b1".parse::<AccountId>().is_err());
+}
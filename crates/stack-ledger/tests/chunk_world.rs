@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use stack_ledger::{compute_block_index, ChunkPosition, ChunkStore, ChunkWorld, Error, ErrorCode, StoredChunk, WorldBlocks, COMMON_CHUNK_SIZE, RELEVANT_CHUNK_SIZE};
+
+type ChunkKey = (i128, i128, i128, u32);
+
+/// Bumps the version of a chunk before each of the first `conflicts` stores, like another server
+/// writing to it at the same time
+#[derive(Default)]
+struct MemoryChunks {
+    chunks: Mutex<HashMap<ChunkKey, StoredChunk>>,
+    common_chunks: HashMap<ChunkKey, Vec<(i16, i32)>>,
+    conflicts: AtomicUsize,
+}
+
+fn get_key(position: ChunkPosition) -> ChunkKey {
+    (position.get_x(), position.get_y(), position.get_z(), position.get_a())
+}
+
+impl ChunkStore for MemoryChunks {
+
+    async fn load_chunk(&self, position: ChunkPosition) -> Result<Option<StoredChunk>, Error> {
+        Ok(self.chunks.lock().unwrap().get(&get_key(position)).cloned())
+    }
+
+    async fn load_common_chunk(&self, position: ChunkPosition) -> Result<Option<Vec<(i16, i32)>>, Error> {
+        Ok(self.common_chunks.get(&get_key(position)).cloned())
+    }
+
+    async fn store_chunk(&self, position: ChunkPosition, blocks: Vec<(i16, i32)>, expected_version: i32) -> Result<bool, Error> {
+        let mut chunks = self.chunks.lock().unwrap();
+        let chunk = chunks.entry(get_key(position)).or_default();
+
+        if self.conflicts.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |conflicts| conflicts.checked_sub(1)).is_ok() {
+            *chunk = StoredChunk::new(chunk.get_blocks().to_vec(), chunk.get_version() + 1);
+        }
+        if chunk.get_version() != expected_version {
+            return Ok(false);
+        }
+
+        *chunk = StoredChunk::new(blocks, expected_version + 1);
+        Ok(true)
+    }
+
+}
+
+// Block (-1, 20, 3) is in chunk (-1, 1, 0) at local position (15, 4, 3)
+fn world_with_block(conflicts: usize) -> ChunkWorld<MemoryChunks> {
+    let store = MemoryChunks::default();
+    let index = (3 * 16 + 4) * 16 + 15;
+    assert_eq!(compute_block_index(RELEVANT_CHUNK_SIZE, -1, 20, 3), index);
+    store.chunks.lock().unwrap().insert((-1, 1, 0, 0), StoredChunk::new(vec![(index, 9)], 3));
+    store.conflicts.store(conflicts, Ordering::Relaxed);
+    ChunkWorld::new(store)
+}
+
+#[tokio::test]
+async fn blocks_are_read_from_their_chunk() {
+    let world = world_with_block(0);
+    assert_eq!(world.get_block(-1, 20, 3, 0).await.unwrap(), Some(9));
+    assert_eq!(world.get_block(15, 20, 3, 0).await.unwrap(), None);
+    assert_eq!(world.get_block(-1, 20, 3, 1).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn breaking_retries_when_the_chunk_changed() {
+    let world = world_with_block(2);
+    assert!(!world.break_block(-1, 20, 3, 0, 8).await.unwrap());
+    assert!(world.break_block(-1, 20, 3, 0, 9).await.unwrap());
+    assert_eq!(world.get_block(-1, 20, 3, 0).await.unwrap(), None);
+    assert!(!world.break_block(-1, 20, 3, 0, 9).await.unwrap());
+}
+
+#[tokio::test]
+async fn breaking_gives_up_on_a_chunk_that_keeps_changing() {
+    let world = world_with_block(usize::MAX);
+    let error = world.break_block(-1, 20, 3, 0, 9).await.unwrap_err();
+    assert!(matches!(error, Error::World(_)));
+    assert_eq!(error.get_code(), ErrorCode::Unavailable);
+    assert!(error.is_retryable());
+}
+
+// Blocks (40, -5, 100) and (41, -5, 100) are in common chunk (1, -1, 1) at local positions
+// (8, 27, 36) and (9, 27, 36), and both in relevant chunk (2, -1, 6)
+#[tokio::test]
+async fn blocks_fall_back_to_the_common_chunk() {
+    let mut store = MemoryChunks::default();
+    let index = ((36 * 32 + 27) * 32 + 8) as u16 as i16;
+    assert_eq!(compute_block_index(COMMON_CHUNK_SIZE, 40, -5, 100), index);
+    store.common_chunks.insert((1, -1, 1, 0), vec![(index, 4), (index + 1, 5)]);
+    let world = ChunkWorld::new(store);
+
+    assert_eq!(world.get_block(40, -5, 100, 0).await.unwrap(), Some(4));
+    assert_eq!(world.get_block(40, -5, 101, 0).await.unwrap(), None);
+    assert!(world.break_block(40, -5, 100, 0, 4).await.unwrap());
+
+    // The relevant chunk overrides the common one from then on
+    assert_eq!(world.get_block(40, -5, 100, 0).await.unwrap(), None);
+    assert_eq!(world.get_block(41, -5, 100, 0).await.unwrap(), Some(5));
+    let chunk = world.get_store().chunks.lock().unwrap()[&(2, -1, 6, 0)].clone();
+    assert_eq!(chunk.get_version(), 1);
+    assert_eq!(chunk.get_blocks(), [(compute_block_index(RELEVANT_CHUNK_SIZE, 41, -5, 100), 5)]);
+}
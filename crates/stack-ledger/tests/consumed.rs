@@ -0,0 +1,28 @@
+use std::collections::HashSet;
+use stack_ledger::compute_region_bit;
+
+#[test]
+fn negative_coordinates_round_down_to_their_region() {
+    let (region, bit) = compute_region_bit(-1, -1, -1, 0);
+    assert_eq!(bit, 4095);
+    assert_eq!(compute_region_bit(-16, -16, -16, 0), (region, 0));
+    assert_ne!(compute_region_bit(-17, -1, -1, 0).0, region);
+    assert_ne!(compute_region_bit(0, -1, -1, 0).0, region);
+    assert_ne!(compute_region_bit(-1, -1, -1, 1).0, region);
+    assert_eq!(compute_region_bit(-16, -3, -1, 0), (region, 13 * 16 + 15));
+}
+
+#[test]
+fn every_block_of_a_negative_region_has_its_own_bit() {
+    let mut bits = HashSet::new();
+    for x in -32..-16 {
+        for y in -16..0 {
+            for z in -48..-32 {
+                let (_, bit) = compute_region_bit(x, y, z, 0);
+                assert!((0..4096).contains(&bit));
+                bits.insert(bit);
+            }
+        }
+    }
+    assert_eq!(bits.len(), 4096);
+}
@@ -0,0 +1,66 @@
+use stack_ledger::{Error, ErrorCode, LootTables};
+
+fn loot_tables(world_seed: u64) -> LootTables {
+    LootTables::from_json(&format!(r#"{{
+        "world_seed": {world_seed},
+        "tables": [{{
+            "block_type": 3,
+            "entries": [
+                {{ "item_type": 7, "weight": 1, "min_qty": 1, "max_qty": 1 }},
+                {{ "item_type": 8, "weight": 3, "min_qty": 2, "max_qty": 5 }}
+            ]
+        }}]
+    }}"#)).unwrap()
+}
+
+#[test]
+fn roll_only_depends_on_the_seed_and_position() {
+    let tables = loot_tables(42);
+    let reloaded = loot_tables(42);
+    let other_seed = loot_tables(43);
+
+    let mut differs = false;
+    for x in 0..100 {
+        let drop = tables.roll(3, x, -64, 12, 0).unwrap();
+        assert_eq!(tables.roll(3, x, -64, 12, 0).unwrap(), drop);
+        assert_eq!(reloaded.roll(3, x, -64, 12, 0).unwrap(), drop);
+        differs |= other_seed.roll(3, x, -64, 12, 0).unwrap() != drop;
+    }
+    assert!(differs);
+}
+
+#[test]
+fn roll_follows_the_weights() {
+    let tables = loot_tables(7);
+    let rolls = 20_000;
+
+    let mut heavy = 0;
+    for x in 0..rolls {
+        let drop = tables.roll(3, x, x * 31, -x, 1).unwrap();
+        match drop.get_type() {
+            7 => assert_eq!(drop.get_qty(), 1),
+            8 => {
+                assert!((2..=5).contains(&drop.get_qty()));
+                heavy += 1;
+            },
+            item_type => panic!("unexpected item type {item_type}"),
+        }
+    }
+
+    // 3 out of 4, within 2%
+    let share = heavy as f64 / rolls as f64;
+    assert!((share - 0.75).abs() < 0.02, "{share}");
+}
+
+#[test]
+fn roll_fails_for_blocks_without_a_table() {
+    let result = loot_tables(1).roll(4, 0, 0, 0, 0);
+    assert!(matches!(result, Err(Error::NotLootable { block_type: 4 })));
+}
+
+#[test]
+fn invalid_file_is_not_a_seed_error() {
+    let error = LootTables::from_json(r#"{ "world_seed": 1 }"#).unwrap_err();
+    assert!(matches!(error, Error::InvalidLootTablesFile(_)));
+    assert_eq!(error.get_code(), ErrorCode::Internal);
+}
@@ -0,0 +1,177 @@
+use stack_ledger::{AccountId, Conservation, Error, InventoryActions, LedgerBackend, LedgerBatch, LedgerTx, MemoryBackend, Operation, Reason, StackLedger, StackSlice, StackUuid};
+
+fn account(account_id: &str) -> AccountId {
+    AccountId::new(account_id).unwrap()
+}
+
+async fn ledger_with_stack(account_id: &AccountId, stack_uuid: StackUuid, qty: u64) -> StackLedger<MemoryBackend> {
+    let ledger = StackLedger::with_backend(MemoryBackend::new());
+    let mut tx = ledger.backend().begin().await.unwrap();
+    tx.create(stack_uuid, 7, None, qty, account_id, None).await.unwrap();
+    tx.commit().await.unwrap();
+    ledger
+}
+
+#[tokio::test]
+async fn stack_uuid_is_consumed_once() {
+    let alice = account("alice");
+    let ledger = ledger_with_stack(&alice, StackUuid::new(1), 10).await;
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    let result = tx.create(StackUuid::new(1), 7, None, 10, &account("bob"), None).await;
+    assert!(matches!(result, Err(Error::AlreadyConsumed { .. })));
+}
+
+#[tokio::test]
+async fn balance_never_goes_below_zero() {
+    let alice = account("alice");
+    let ledger = ledger_with_stack(&alice, StackUuid::new(1), 10).await;
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    let result = tx.destroy(StackUuid::new(1), 7, None, &alice, 11, None).await;
+    assert!(matches!(result, Err(Error::NotEnoughBalance { qty: 11, balance: 10, .. })));
+    drop(tx);
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    tx.destroy(StackUuid::new(1), 7, None, &alice, 10, None).await.unwrap();
+    let result = tx.destroy(StackUuid::new(1), 7, None, &alice, 1, None).await;
+    assert!(matches!(result, Err(Error::NotEnoughBalance { qty: 1, balance: 0, .. })));
+}
+
+#[tokio::test]
+async fn sequence_numbers_are_consecutive_per_account_and_stack() {
+    let alice = account("alice");
+    let bob = account("bob");
+    let stack_uuid = StackUuid::new(1);
+    let ledger = ledger_with_stack(&alice, stack_uuid, 10).await;
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    tx.split(stack_uuid, 7, Some(0), None, &alice, &bob, 4, None).await.unwrap();
+    tx.split(stack_uuid, 7, Some(1), None, &alice, &bob, 4, None).await.unwrap();
+    tx.split(stack_uuid, 7, Some(0), None, &bob, &alice, 1, None).await.unwrap_err();
+    tx.commit().await.unwrap();
+
+    let sequence_numbers = |account_id: &AccountId, entries: &[stack_ledger::LedgerEntry]| {
+        entries
+            .iter()
+            .filter(|entry| entry.get_account_id() == account_id)
+            .map(|entry| entry.get_sequence_number())
+            .collect::<Vec<_>>()
+    };
+    let entries = ledger.backend().get_entries().await;
+    assert_eq!(sequence_numbers(&alice, &entries), vec![0, 1, 2]);
+    assert_eq!(sequence_numbers(&bob, &entries), vec![0, 1]);
+    assert_eq!(entries.last().unwrap().get_operation(), Operation::SplitIn);
+    assert_eq!(ledger.backend().get_balance(&alice, stack_uuid).await, Some(2));
+    assert_eq!(ledger.backend().get_balance(&bob, stack_uuid).await, Some(8));
+}
+
+#[tokio::test]
+async fn dropped_transaction_writes_nothing() {
+    let alice = account("alice");
+    let ledger = ledger_with_stack(&alice, StackUuid::new(1), 10).await;
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    tx.destroy(StackUuid::new(1), 7, None, &alice, 3, None).await.unwrap();
+    tx.create(StackUuid::new(2), 7, None, 5, &alice, None).await.unwrap();
+    drop(tx);
+
+    assert_eq!(ledger.backend().get_balance(&alice, StackUuid::new(1)).await, Some(10));
+    assert_eq!(ledger.backend().get_balance(&alice, StackUuid::new(2)).await, None);
+    assert_eq!(ledger.backend().get_entries().await.len(), 1);
+
+    // The uuid was given back too
+    let mut tx = ledger.backend().begin().await.unwrap();
+    tx.create(StackUuid::new(2), 7, None, 5, &alice, None).await.unwrap();
+    tx.commit().await.unwrap();
+    assert_eq!(ledger.backend().get_balance(&alice, StackUuid::new(2)).await, Some(5));
+}
+
+#[tokio::test]
+async fn batch_receipt_holds_the_inserted_entries() {
+    let alice = account("alice");
+    let bob = account("bob");
+    let ledger = ledger_with_stack(&alice, StackUuid::new(1), 10).await;
+
+    let batch = LedgerBatch::new()
+        .split(StackUuid::new(1), 7, Some(0), None, &alice, &bob, 4)
+        .create(StackUuid::new(2), 8, None, &bob, 1);
+    let receipt = ledger.execute_batch(&batch).await.unwrap();
+
+    let entries = ledger.backend().get_entries().await;
+    assert_eq!(receipt.get_entries().len(), 3);
+    for (receipt_entry, entry) in receipt.get_entries().iter().zip(&entries[1..]) {
+        assert_eq!(receipt_entry.get_key(), entry.get_key());
+        assert_eq!(receipt_entry.get_sequence_number(), entry.get_sequence_number());
+        assert_eq!(Some(receipt_entry.get_entry_hash()), entry.get_entry_hash());
+    }
+}
+
+#[tokio::test]
+async fn credit_checks_the_recipient_version() {
+    let alice = account("alice");
+    let bob = account("bob");
+    let stack_uuid = StackUuid::new(1);
+    let ledger = ledger_with_stack(&alice, stack_uuid, 10).await;
+
+    // Bob doesn't hold the stack yet
+    let mut tx = ledger.backend().begin().await.unwrap();
+    tx.split(stack_uuid, 7, None, Some(0), &alice, &bob, 4, None).await.unwrap();
+    tx.commit().await.unwrap();
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    let result = tx.split(stack_uuid, 7, None, Some(3), &alice, &bob, 1, None).await;
+    assert!(matches!(result, Err(Error::StaleVersion { expected: 3, actual: 0, .. })));
+    drop(tx);
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    let (_, credit) = tx.split(stack_uuid, 7, None, Some(0), &alice, &bob, 1, None).await.unwrap();
+    assert_eq!(credit.get_sequence_number(), 1);
+}
+
+#[tokio::test]
+async fn replayed_craft_mints_once() {
+    let alice = account("alice");
+    let ledger = ledger_with_stack(&alice, StackUuid::new(1), 10).await;
+    let inputs = [StackSlice::new(StackUuid::new(1), 3, 7).with_sequence_number(0)];
+
+    let crafted = ledger.craft(&alice, &inputs, 1, 42, 1, 8).await.unwrap();
+    let result = ledger.craft(&alice, &inputs, 1, 42, 1, 8).await;
+    assert!(matches!(result, Err(Error::StaleVersion { expected: 0, actual: 1, .. })));
+
+    assert_eq!(ledger.backend().get_balance(&alice, crafted).await, Some(1));
+    assert_eq!(ledger.backend().get_balance(&alice, StackUuid::new(1)).await, Some(7));
+
+    let result = ledger.craft(&alice, &[StackSlice::new(StackUuid::new(1), 3, 7)], 1, 43, 1, 8).await;
+    assert!(matches!(result, Err(Error::MissingSequenceNumber { .. })));
+}
+
+#[tokio::test]
+async fn frozen_account_freezes_its_briefcases() {
+    let briefcase = AccountId::new_briefcase(&account("alice"), 1).unwrap();
+    let ledger = ledger_with_stack(&briefcase, StackUuid::new(1), 10).await;
+    ledger.backend().freeze_account(&account("alice"), "chargeback", None).await.unwrap();
+
+    let mut tx = ledger.backend().begin().await.unwrap();
+    let result = tx.destroy(StackUuid::new(1), 7, None, &briefcase, 1, None).await;
+    assert!(matches!(result, Err(Error::AccountFrozen { account_id, .. }) if account_id == briefcase));
+    drop(tx);
+
+    ledger.backend().unfreeze_account(&account("alice")).await.unwrap();
+    let mut tx = ledger.backend().begin().await.unwrap();
+    tx.destroy(StackUuid::new(1), 7, None, &briefcase, 1, None).await.unwrap();
+}
+
+#[tokio::test]
+async fn craft_batch_rejects_conservation() {
+    let alice = account("alice");
+    let ledger = ledger_with_stack(&alice, StackUuid::new(1), 10).await;
+
+    let craft = LedgerBatch::new()
+        .destroy(StackUuid::new(1), 7, None, &alice, 2)
+        .create(StackUuid::new(2), 8, None, &alice, 1)
+        .reason(Reason::Recipe { recipe_id: 3, nonce: 0 });
+    let result = ledger.execute_batch(&craft.clone().conservation(Conservation::Balanced)).await;
+    assert!(matches!(result, Err(Error::ConservationOnCraft { recipe_id: 3, .. })));
+    ledger.execute_batch(&craft).await.unwrap();
+}
@@ -0,0 +1,43 @@
+use stack_ledger::{compute_inventory_root, compute_leaf_hash, StackUuid, EMPTY_ROOT};
+
+// A dumped `latest` row: key, stack uuid, sequence number, balance, item type and entry hash
+type Row = (Vec<u8>, u128, i32, i64, i32, Vec<u8>);
+
+fn compute_leaves(rows: &[Row]) -> Vec<(Vec<u8>, [u8; 32])> {
+    rows.iter()
+        .map(|(key, stack_uuid, sequence_number, balance, item_type, entry_hash)| {
+            let hash = compute_leaf_hash(key, StackUuid::new(*stack_uuid), *sequence_number, *balance, *item_type, entry_hash);
+            (key.clone(), hash)
+        })
+        .collect()
+}
+
+fn dump() -> Vec<Row> {
+    (0..5u8)
+        .map(|n| (vec![0x40 - n * 7, n], 100 + n as u128, n as i32, 10 * n as i64, 3, vec![n; 32]))
+        .collect()
+}
+
+#[test]
+fn root_only_depends_on_the_rows() {
+    let rows = dump();
+    let root = compute_inventory_root(compute_leaves(&rows));
+
+    let mut reversed = rows.clone();
+    reversed.reverse();
+    assert_eq!(compute_inventory_root(compute_leaves(&reversed)), root);
+
+    let mut edited = rows.clone();
+    edited[2].3 += 1;
+    assert_ne!(compute_inventory_root(compute_leaves(&edited)), root);
+
+    // A removed stack changes the root even though every other row is untouched
+    assert_ne!(compute_inventory_root(compute_leaves(&rows[1..])), root);
+}
+
+#[test]
+fn small_trees() {
+    assert_eq!(compute_inventory_root(Vec::new()), EMPTY_ROOT);
+    let leaves = compute_leaves(&dump()[..1]);
+    assert_eq!(compute_inventory_root(leaves.clone()), leaves[0].1);
+}
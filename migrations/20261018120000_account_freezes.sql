@@ -0,0 +1,11 @@
+-- Frozen accounts can still log in, but cannot debit any of their stacks
+CREATE TABLE frozen_accounts (
+    account_id TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    expires_at BIGINT -- unix timestamp, NULL means frozen until manually unfrozen
+);
+
+ALTER TABLE frozen_accounts ADD CONSTRAINT exc_frozen_accounts_account_id
+EXCLUDE USING hash (
+    account_id WITH =
+);
@@ -0,0 +1,16 @@
+CREATE TYPE ledger_operation AS ENUM ('create', 'destroy', 'split_out', 'split_in');
+
+ALTER TABLE ledger ADD COLUMN operation ledger_operation;
+ALTER TABLE ledger ADD COLUMN reason JSONB;
+
+-- Old rows can't tell a destroy from the debit side of a split, so every debit is a destroy
+UPDATE ledger SET operation = CASE
+    WHEN qty < 0 THEN 'destroy'::ledger_operation
+    WHEN key = (SELECT min(first.key) FROM ledger first WHERE first.stack_uuid = ledger.stack_uuid) THEN 'create'::ledger_operation
+    ELSE 'split_in'::ledger_operation
+END;
+
+ALTER TABLE ledger ALTER COLUMN operation SET NOT NULL;
+
+-- For support lookups by trade id, recipe, ticket...
+CREATE INDEX idx_ledger_reason ON ledger USING gin (reason jsonb_path_ops);
@@ -0,0 +1,7 @@
+-- Existing rows keep their legacy (V0) keys until StackLedger::rekey_batch moves them
+ALTER TABLE latest ADD COLUMN key_version SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE ledger ADD COLUMN key_version SMALLINT NOT NULL DEFAULT 0;
+
+-- Only holds the rows still waiting to be rekeyed, so it empties itself as the rekey goes
+CREATE INDEX idx_latest_legacy_keys ON latest USING btree (key_version) WHERE key_version = 0;
+CREATE INDEX idx_ledger_legacy_keys ON ledger USING btree (key_version) WHERE key_version = 0;
@@ -0,0 +1,77 @@
+-- ledger and latest are hash partitioned by account_id, every StackLedger query has to filter by
+-- it so Postgres only touches one partition. New partitions are managed by PartitionManager
+
+-- Partitioned tables can't have exclusion constraints. Both keys are derived from the account_id,
+-- so making them unique per account is just as strict
+ALTER TABLE latest RENAME TO latest_unpartitioned;
+ALTER TABLE latest_unpartitioned DROP CONSTRAINT exc_latest_key;
+DROP INDEX idx_latest_key;
+DROP INDEX idx_latest_legacy_keys;
+
+CREATE TABLE latest (
+    key BYTEA NOT NULL,
+    account_id TEXT NOT NULL,
+    stack_uuid BYTEA NOT NULL,
+    sequence_number INTEGER NOT NULL CHECK (sequence_number >= 0),
+    balance INTEGER NOT NULL CHECK (balance >= 0),
+    item_type INTEGER NOT NULL,
+    key_version SMALLINT NOT NULL DEFAULT 0,
+    CONSTRAINT no_sub_zero_balance CHECK (balance >= 0)
+) PARTITION BY HASH (account_id);
+
+ALTER TABLE latest ADD CONSTRAINT uq_latest_key UNIQUE (account_id, key);
+
+CREATE INDEX idx_latest_legacy_keys ON latest USING btree (key_version) WHERE key_version = 0;
+
+ALTER TABLE ledger RENAME TO ledger_unpartitioned;
+ALTER TABLE ledger_unpartitioned DROP CONSTRAINT exc_ledger_composite;
+DROP INDEX idx_ledger_key;
+DROP INDEX idx_ledger_reason;
+DROP INDEX idx_ledger_legacy_keys;
+
+CREATE TABLE ledger (
+    key BIGINT GENERATED ALWAYS AS IDENTITY,
+    account_id TEXT NOT NULL,
+    stack_uuid BYTEA NOT NULL,
+    sequence_number INTEGER NOT NULL CHECK (sequence_number >= 0),
+    composite BYTEA NOT NULL,
+    qty INTEGER NOT NULL,
+    balance INTEGER NOT NULL CHECK (balance >= 0),
+    item_type INTEGER NOT NULL,
+    operation ledger_operation NOT NULL,
+    reason JSONB,
+    key_version SMALLINT NOT NULL DEFAULT 0,
+    CONSTRAINT no_sub_zero_balance CHECK (balance >= 0)
+) PARTITION BY HASH (account_id);
+
+ALTER TABLE ledger ADD CONSTRAINT uq_ledger_composite UNIQUE (account_id, composite);
+
+-- To prune
+CREATE INDEX idx_ledger_key ON ledger USING hash (key);
+
+CREATE INDEX idx_ledger_reason ON ledger USING gin (reason jsonb_path_ops);
+
+CREATE INDEX idx_ledger_legacy_keys ON ledger USING btree (key_version) WHERE key_version = 0;
+
+-- Same naming as PartitionManager: {table}_p{modulus}_{remainder}
+DO $$
+BEGIN
+    FOR remainder IN 0..7 LOOP
+        EXECUTE format('CREATE TABLE latest_p8_%s PARTITION OF latest FOR VALUES WITH (MODULUS 8, REMAINDER %s)', remainder, remainder);
+        EXECUTE format('CREATE TABLE ledger_p8_%s PARTITION OF ledger FOR VALUES WITH (MODULUS 8, REMAINDER %s)', remainder, remainder);
+    END LOOP;
+END $$;
+
+INSERT INTO latest (key, account_id, stack_uuid, sequence_number, balance, item_type, key_version)
+SELECT key, account_id, stack_uuid, sequence_number, balance, item_type, key_version
+FROM latest_unpartitioned;
+
+INSERT INTO ledger (key, account_id, stack_uuid, sequence_number, composite, qty, balance, item_type, operation, reason, key_version)
+OVERRIDING SYSTEM VALUE
+SELECT key, account_id, stack_uuid, sequence_number, composite, qty, balance, item_type, operation, reason, key_version
+FROM ledger_unpartitioned;
+
+SELECT setval(pg_get_serial_sequence('ledger', 'key'), COALESCE(max(key), 0) + 1, false) FROM ledger;
+
+DROP TABLE latest_unpartitioned;
+DROP TABLE ledger_unpartitioned;
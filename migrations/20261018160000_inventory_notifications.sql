@@ -0,0 +1,16 @@
+-- InventoryManager caches inventories and drops them when these fire. pg_notify only delivers on
+-- commit and folds duplicate payloads of the same transaction
+CREATE FUNCTION notify_inventory_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('inventory_changed', COALESCE(NEW.account_id, OLD.account_id));
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_latest_inventory_changed
+AFTER INSERT OR UPDATE OR DELETE ON latest
+FOR EACH ROW EXECUTE FUNCTION notify_inventory_changed();
+
+CREATE TRIGGER trg_inventories_inventory_changed
+AFTER INSERT OR UPDATE OR DELETE ON inventories
+FOR EACH ROW EXECUTE FUNCTION notify_inventory_changed();
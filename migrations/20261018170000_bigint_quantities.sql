@@ -0,0 +1,7 @@
+-- Quantities and balances are checked 64-bit values in StackLedger, rewrites every partition
+ALTER TABLE ledger
+    ALTER COLUMN qty TYPE BIGINT,
+    ALTER COLUMN balance TYPE BIGINT;
+
+ALTER TABLE latest
+    ALTER COLUMN balance TYPE BIGINT;
@@ -0,0 +1,4 @@
+-- Every ledger entry hashes its contents with the hash of the previous entry of the same account
+-- and stack, latest keeps the newest one. NULL for entries written before the chain existed
+ALTER TABLE ledger ADD COLUMN entry_hash BYTEA;
+ALTER TABLE latest ADD COLUMN entry_hash BYTEA;
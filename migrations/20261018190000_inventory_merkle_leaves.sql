@@ -0,0 +1,3 @@
+-- Merkle leaf of each latest row, kept up to date by the same statement that changes the row.
+-- NULL rows get their leaf computed when a commitment is read
+ALTER TABLE latest ADD COLUMN leaf_hash BYTEA;
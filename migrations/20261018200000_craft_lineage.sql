@@ -0,0 +1,13 @@
+-- Inputs consumed by each craft, written in the same transaction as the crafted stack
+CREATE TABLE craft_inputs (
+    output_uuid BYTEA NOT NULL,
+    input_uuid BYTEA NOT NULL,
+    account_id TEXT NOT NULL,
+    sequence_number INTEGER NOT NULL, -- of the destroy entry of the input
+    qty BIGINT NOT NULL CHECK (qty > 0)
+);
+
+CREATE INDEX idx_craft_inputs_output_uuid ON craft_inputs USING hash (output_uuid);
+
+-- Finds the entry that created a stack when walking a lineage
+CREATE INDEX idx_ledger_creates ON ledger USING hash (stack_uuid) WHERE operation = 'create';
@@ -0,0 +1,10 @@
+-- Rows can move to another account when one is erased, both have to drop their cached inventory
+CREATE OR REPLACE FUNCTION notify_inventory_changed() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW.account_id IS DISTINCT FROM OLD.account_id THEN
+        PERFORM pg_notify('inventory_changed', OLD.account_id);
+    END IF;
+    PERFORM pg_notify('inventory_changed', COALESCE(NEW.account_id, OLD.account_id));
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
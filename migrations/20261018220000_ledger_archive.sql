@@ -0,0 +1,37 @@
+-- Unix time the entry was written. Entries from before this migration get the time it ran
+ALTER TABLE ledger ADD COLUMN created_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
+
+-- Entries are appended in time order, so a BRIN index finds old ones without slowing inserts
+CREATE INDEX idx_ledger_created_at ON ledger USING brin (created_at);
+
+-- Old entries moved out of ledger by StackLedger::archive_batch. The newest entry of every
+-- account and stack always stays in ledger
+CREATE TABLE ledger_archive (
+    key BIGINT NOT NULL,
+    account_id TEXT NOT NULL,
+    stack_uuid BYTEA NOT NULL,
+    sequence_number INTEGER NOT NULL,
+    composite BYTEA NOT NULL,
+    qty BIGINT NOT NULL,
+    balance BIGINT NOT NULL,
+    item_type INTEGER NOT NULL,
+    operation ledger_operation NOT NULL,
+    reason JSONB,
+    key_version SMALLINT NOT NULL,
+    entry_hash BYTEA,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX idx_ledger_archive_account_key ON ledger_archive USING btree (account_id, key);
+
+CREATE INDEX idx_ledger_archive_stack_uuid ON ledger_archive USING hash (stack_uuid);
+
+CREATE INDEX idx_ledger_archive_reason ON ledger_archive USING gin (reason jsonb_path_ops);
+
+-- Every entry ever written, history, lineage and audit reads go through it
+CREATE VIEW ledger_history AS
+SELECT key, account_id, stack_uuid, sequence_number, composite, qty, balance, item_type, operation, reason, key_version, entry_hash, created_at
+FROM ledger
+UNION ALL
+SELECT key, account_id, stack_uuid, sequence_number, composite, qty, balance, item_type, operation, reason, key_version, entry_hash, created_at
+FROM ledger_archive;
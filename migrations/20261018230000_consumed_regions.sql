@@ -0,0 +1,9 @@
+-- Looted coordinates, one bit per block of a 16x16x16 region (512 bytes per row). Stacks that
+-- aren't loot stay in consumed, loot created before this is moved by compact_consumed_batch
+CREATE TABLE consumed_regions (
+    region_key BYTEA NOT NULL,
+    bits BYTEA NOT NULL
+);
+
+-- Exclusion constraints don't support ON CONFLICT DO UPDATE
+ALTER TABLE consumed_regions ADD CONSTRAINT uq_consumed_regions_region_key UNIQUE (region_key);
@@ -0,0 +1,34 @@
+-- Leaves are ordered by when their row was first written instead of by key, so a write to latest
+-- only changes the nodes from its leaf to the root. Existing rows keep their order by key
+ALTER TABLE latest ADD COLUMN leaf_index INTEGER;
+
+UPDATE latest
+SET leaf_index = ordered.leaf_index
+FROM (
+    SELECT account_id, key, ROW_NUMBER() OVER (PARTITION BY account_id ORDER BY key) - 1 AS leaf_index
+    FROM latest
+) AS ordered
+WHERE latest.account_id = ordered.account_id AND latest.key = ordered.key;
+
+ALTER TABLE latest ALTER COLUMN leaf_index SET NOT NULL;
+ALTER TABLE latest ADD CONSTRAINT uq_latest_leaf_index UNIQUE (account_id, leaf_index);
+
+-- Root of each account's tree, updated in the same transaction as latest. Accounts without a row
+-- haven't been written since and get their root computed when it is read
+CREATE TABLE inventory_commitments (
+    account_id TEXT NOT NULL,
+    root BYTEA NOT NULL,
+    leaf_count INTEGER NOT NULL
+);
+
+ALTER TABLE inventory_commitments ADD CONSTRAINT uq_inventory_commitments_account_id UNIQUE (account_id);
+
+-- Every node of those trees, level 0 holds the leaves
+CREATE TABLE inventory_merkle_nodes (
+    account_id TEXT NOT NULL,
+    level SMALLINT NOT NULL,
+    position INTEGER NOT NULL,
+    hash BYTEA NOT NULL
+);
+
+ALTER TABLE inventory_merkle_nodes ADD CONSTRAINT uq_inventory_merkle_nodes_position UNIQUE (account_id, level, position);
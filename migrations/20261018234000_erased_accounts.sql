@@ -0,0 +1,9 @@
+-- Accounts that were erased, by an HMAC-SHA256 of their old id under a key only the servers hold, so
+-- the id itself isn't kept and can't be guessed back. Stacks can no longer be created for or sent to
+-- them, or to their briefcases
+CREATE TABLE erased_accounts (
+    account_hash BYTEA NOT NULL,
+    erased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT
+);
+
+ALTER TABLE erased_accounts ADD CONSTRAINT uq_erased_accounts_account_hash UNIQUE (account_hash);
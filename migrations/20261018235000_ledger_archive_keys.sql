@@ -0,0 +1,5 @@
+-- Exports read ledger_history in key order
+CREATE INDEX idx_ledger_archive_key ON ledger_archive USING btree (key);
+
+-- Archived entries with a legacy key still have to be rekeyed
+CREATE INDEX idx_ledger_archive_legacy_keys ON ledger_archive USING btree (key_version) WHERE key_version = 0;
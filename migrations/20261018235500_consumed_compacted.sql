@@ -0,0 +1,5 @@
+-- Written once compact_consumed_batch has moved every looted stack to consumed_regions, loot is
+-- no longer looked up by uuid in consumed after that
+CREATE TABLE consumed_compacted (
+    compacted_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT
+);
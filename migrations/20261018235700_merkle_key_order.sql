@@ -0,0 +1,6 @@
+-- Leaves are ordered by key, so anyone can rebuild a root from the account's rows alone. Stored
+-- trees were built in write order and get rebuilt the next time their account is written
+ALTER TABLE latest DROP CONSTRAINT uq_latest_leaf_index;
+ALTER TABLE latest DROP COLUMN leaf_index;
+
+TRUNCATE inventory_merkle_nodes, inventory_commitments;